@@ -0,0 +1,113 @@
+-- The tables behind the Storage trait, for PostgreSQL. Everything else lives in schema.sql and is MySQL only.
+-- Identifiers are unquoted, so they're folded to lower case.
+
+CREATE EXTENSION IF NOT EXISTS timescaledb;
+
+CREATE TABLE Exchange
+(
+    name         varchar(64) DEFAULT NULL,
+    code         varchar(10) NOT NULL,
+    operatingMIC varchar(20) DEFAULT NULL,
+    country      varchar(20) DEFAULT NULL,
+    currency     char(3)     DEFAULT NULL,
+    countryISO2  char(2)     DEFAULT NULL,
+    countryISO3  char(3)     DEFAULT NULL,
+    PRIMARY KEY (code)
+);
+
+CREATE TABLE ExchangeSymbol
+(
+    name         varchar(250) DEFAULT NULL,
+    code         varchar(12) NOT NULL,
+    exchange     varchar(10) NOT NULL,
+    type         varchar(50)  DEFAULT NULL,
+    country      varchar(32)  DEFAULT NULL,
+    currency     char(3)      DEFAULT NULL,
+    isin         char(12)     DEFAULT NULL,
+    realExchange varchar(10)  DEFAULT NULL,
+    delisted     boolean      NOT NULL DEFAULT FALSE,
+    firstSeen    date         DEFAULT NULL,
+    lastSeen     date         DEFAULT NULL,
+    PRIMARY KEY (code, exchange),
+    FOREIGN KEY (exchange) REFERENCES Exchange (code)
+);
+CREATE INDEX idx_exchangesymbol_type ON ExchangeSymbol (type);
+CREATE INDEX idx_exchangesymbol_name ON ExchangeSymbol (name);
+CREATE INDEX idx_exchangesymbol_exchange_seen ON ExchangeSymbol (exchange, firstSeen, lastSeen);
+
+CREATE TABLE SymbolChangeLog
+(
+    id         serial       NOT NULL,
+    code       varchar(12)  NOT NULL,
+    exchange   varchar(10)  NOT NULL,
+    changeType varchar(7)   NOT NULL CHECK (changeType IN ('ADDED', 'REMOVED', 'CHANGED')),
+    field      varchar(16)  DEFAULT NULL,
+    oldValue   varchar(250) DEFAULT NULL,
+    newValue   varchar(250) DEFAULT NULL,
+    changedAt  timestamp    DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    FOREIGN KEY (exchange) REFERENCES Exchange (code)
+);
+CREATE INDEX idx_symbolchangelog_code_exchange ON SymbolChangeLog (code, exchange);
+CREATE INDEX idx_symbolchangelog_changedat ON SymbolChangeLog (changedAt);
+
+CREATE TABLE DownloadedSymbol
+(
+    code       varchar(12) NOT NULL,
+    exchange   varchar(10) NOT NULL,
+    updated_at timestamp DEFAULT CURRENT_TIMESTAMP,
+    isEmpty    boolean   DEFAULT FALSE,
+
+    PRIMARY KEY (code, exchange),
+    FOREIGN KEY (code, exchange) REFERENCES ExchangeSymbol (code, exchange)
+);
+
+-- Doubles rather than reals, since sqlx won't decode a real into f64
+CREATE TABLE StockPrice
+(
+    code      varchar(12)      NOT NULL,
+    exchange  varchar(10)      NOT NULL,
+    timestamp timestamp        NOT NULL,
+    gmtoffset integer          DEFAULT NULL,
+    open      double precision DEFAULT NULL,
+    high      double precision DEFAULT NULL,
+    low       double precision DEFAULT NULL,
+    close     double precision DEFAULT NULL,
+    volume    bigint           DEFAULT NULL,
+    PRIMARY KEY (code, exchange, timestamp),
+    FOREIGN KEY (code, exchange) REFERENCES ExchangeSymbol (code, exchange)
+);
+SELECT create_hypertable('stockprice', 'timestamp', chunk_time_interval => INTERVAL '1 month');
+
+-- Bars the gate rejected, with the rule each broke
+CREATE TABLE QuarantinedPrice
+(
+    id             serial      NOT NULL,
+    code           varchar(12) NOT NULL,
+    exchange       varchar(10) NOT NULL,
+    source         varchar(8)  NOT NULL CHECK (source IN ('INTRADAY', 'EOD')),
+    timestamp      timestamp   NOT NULL,
+    gmtoffset      integer          DEFAULT NULL,
+    open           double precision DEFAULT NULL,
+    high           double precision DEFAULT NULL,
+    low            double precision DEFAULT NULL,
+    close          double precision DEFAULT NULL,
+    adjusted_close double precision DEFAULT NULL,
+    volume         bigint           DEFAULT NULL,
+    rule           varchar(20) NOT NULL,
+    quarantinedAt  timestamp DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    FOREIGN KEY (code, exchange) REFERENCES ExchangeSymbol (code, exchange)
+);
+CREATE INDEX idx_quarantinedprice_code_exchange ON QuarantinedPrice (code, exchange);
+
+CREATE TABLE StageDone
+(
+    exchange    varchar(10),
+    stage       varchar(16) CHECK (stage IN ('INTRADAY', 'EOD', 'FUNDAMENTAL', 'NEWS', 'CALENDAR', 'INSIDER',
+                                             'OPTIONS', 'BONDS', 'MARKET_CAP', 'SENTIMENT', 'AGGREGATE')),
+    lastUpdated timestamp DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (exchange, stage),
+    FOREIGN KEY (exchange) REFERENCES Exchange (code)
+);
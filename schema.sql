@@ -17,8 +17,14 @@ CREATE TABLE `CrossReferencedSymbols`
     `exchange` varchar(10)  NOT NULL,
     `name`     varchar(100) NOT NULL,
     `isin`     varchar(12) DEFAULT NULL,
+    `cusip`    char(9)     DEFAULT NULL,
+    `figi`     char(12)    DEFAULT NULL,
     PRIMARY KEY (`id`),
-    FOREIGN KEY (`code`) REFERENCES `ExchangeSymbol` (`code`),
+    UNIQUE KEY `code_exchange` (`code`, `exchange`),
+    KEY `idx_isin` (`isin`),
+    KEY `idx_cusip` (`cusip`),
+    KEY `idx_figi` (`figi`),
+    -- No key on ExchangeSymbol, the listing a mapping points to is often not synced yet
     FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
 ) ENGINE = InnoDB
   DEFAULT CHARSET = utf8mb4
@@ -125,10 +131,14 @@ CREATE TABLE `ExchangeSymbol`
     `currency`     char(3)      DEFAULT NULL,
     `isin`         char(12)     DEFAULT NULL,
     `realExchange` varchar(10)  DEFAULT NULL,
+    `delisted`     boolean      NOT NULL DEFAULT FALSE,
+    `firstSeen`    date         DEFAULT NULL,
+    `lastSeen`     date         DEFAULT NULL,
     PRIMARY KEY (`code`, `exchange`),
     UNIQUE KEY `code` (`code`, `exchange`, `isin`, `name`, `type`),
     KEY `idx_type` (`type`),
     KEY `idx_name` (`name`),
+    KEY `idx_exchange_seen` (`exchange`, `firstSeen`, `lastSeen`),
     FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
 ) ENGINE = InnoDB
   DEFAULT CHARSET = utf8mb4
@@ -547,6 +557,8 @@ CREATE TABLE `InsiderTransaction`
 (
     `Id`                          int unsigned NOT NULL AUTO_INCREMENT,
     `FundamentalDataId`           int unsigned DEFAULT NULL,
+    `code`                        varchar(12)  DEFAULT NULL,
+    `exchange`                    varchar(10)  DEFAULT NULL,
     `date`                        date         DEFAULT NULL,
     `ownerName`                   varchar(50)  DEFAULT NULL,
     `transactionDate`             date         DEFAULT NULL,
@@ -556,7 +568,16 @@ CREATE TABLE `InsiderTransaction`
     `transactionAcquiredDisposed` varchar(5)   DEFAULT NULL,
     `postTransactionAmount`       int          DEFAULT NULL,
     `secLink`                     varchar(150) DEFAULT NULL,
+    -- Filings without a link would never collide on secLink alone, so they're keyed on their contents
+    `filingKey`                   varchar(255) AS (COALESCE(`secLink`,
+                                                           CONCAT_WS('|', `code`, `exchange`, `date`, `ownerName`,
+                                                                     `transactionDate`, `transactionCode`,
+                                                                     `transactionAmount`, `transactionPrice`,
+                                                                     `transactionAcquiredDisposed`,
+                                                                     `postTransactionAmount`))) STORED NOT NULL,
     PRIMARY KEY (`Id`),
+    UNIQUE KEY `filingKey` (`filingKey`),
+    KEY `idx_code_exchange_date` (`code`, `exchange`, `date`),
     FOREIGN KEY (`FundamentalDataId`) REFERENCES `FundamentalMetadata` (`Id`)
 ) ENGINE = InnoDB
   DEFAULT CHARSET = utf8mb4
@@ -577,6 +598,10 @@ CREATE TABLE `OutstandingShare`
   COLLATE = utf8mb4_0900_ai_ci;
 
 
+-- Partitioned by month, see the partitions command, which is best run once before the first dump so
+-- pfuture is still empty when it's split up. MySQL can't partition tables with foreign keys. The unique key
+-- is what lets INSERT IGNORE skip bars that are already stored.
+-- p202010 also holds everything before, intraday history starts in October 2020 (1604188800 is 2020-11-01 UTC).
 CREATE TABLE `StockPrice`
 (
     `code`      varchar(12) NOT NULL,
@@ -587,12 +612,15 @@ CREATE TABLE `StockPrice`
     `high`      float            DEFAULT NULL,
     `low`       float            DEFAULT NULL,
     `close`     float            DEFAULT NULL,
-    `volume`    int              DEFAULT NULL,
-    FOREIGN KEY (`code`) REFERENCES `ExchangeSymbol` (`code`),
-    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+    `volume`    bigint           DEFAULT NULL,
+    UNIQUE KEY `idx_code_exchange_timestamp` (`code`, `exchange`, `timestamp`)
 ) ENGINE = InnoDB
   DEFAULT CHARSET = utf8mb4
-  COLLATE = utf8mb4_0900_ai_ci;
+  COLLATE = utf8mb4_0900_ai_ci
+  PARTITION BY RANGE (UNIX_TIMESTAMP(`timestamp`)) (
+    PARTITION p202010 VALUES LESS THAN (1604188800),
+    PARTITION pfuture VALUES LESS THAN MAXVALUE
+  );
 
 CREATE TABLE `StockPriceEOD`
 (
@@ -656,9 +684,340 @@ CREATE TABLE `NewsUpdated`
 CREATE TABLE StageDone
 (
     exchange    varchar(10),
-    stage       ENUM ('INTRADAY','EOD','FUNDAMENTAL','NEWS'),
+    stage       ENUM ('INTRADAY','EOD','FUNDAMENTAL','NEWS','CALENDAR','INSIDER','OPTIONS','BONDS','MARKET_CAP','SENTIMENT','AGGREGATE'),
     lastUpdated TIMESTAMP DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
 
     PRIMARY KEY (exchange, stage),
     FOREIGN KEY (exchange) REFERENCES Exchange (code)
-);
\ No newline at end of file
+);
+CREATE TABLE `LatestQuote`
+(
+    `code`          varchar(12) NOT NULL,
+    `exchange`      varchar(10) NOT NULL,
+    `timestamp`     timestamp   NULL DEFAULT NULL,
+    `gmtoffset`     tinyint          DEFAULT NULL,
+    `open`          float            DEFAULT NULL,
+    `high`          float            DEFAULT NULL,
+    `low`           float            DEFAULT NULL,
+    `close`         float            DEFAULT NULL,
+    `volume`        bigint           DEFAULT NULL,
+    `previousClose` float            DEFAULT NULL,
+    `change`        float            DEFAULT NULL,
+    `changePercent` float            DEFAULT NULL,
+    `updatedAt`     timestamp   NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (`code`, `exchange`),
+    FOREIGN KEY (`code`) REFERENCES `ExchangeSymbol` (`code`),
+    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+-- Calendars aren't tied to ExchangeSymbol since upcoming IPOs aren't listed yet
+CREATE TABLE `EarningsCalendar`
+(
+    `code`              varchar(12) NOT NULL,
+    `exchange`          varchar(10) NOT NULL,
+    `reportDate`        date        NOT NULL,
+    `periodDate`        date        DEFAULT NULL,
+    `beforeAfterMarket` varchar(16) DEFAULT NULL,
+    `currency`          char(3)     DEFAULT NULL,
+    `actual`            float       DEFAULT NULL,
+    `estimate`          float       DEFAULT NULL,
+    `difference`        float       DEFAULT NULL,
+    `percent`           float       DEFAULT NULL,
+    `updatedAt`         timestamp   NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (`code`, `exchange`, `reportDate`),
+    KEY `idx_reportDate` (`reportDate`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `IpoCalendar`
+(
+    `code`            varchar(12)  NOT NULL,
+    `exchange`        varchar(10)  NOT NULL,
+    `startDate`       date         DEFAULT NULL,
+    `name`            varchar(250) DEFAULT NULL,
+    `listingExchange` varchar(64)  DEFAULT NULL,
+    `currency`        char(3)      DEFAULT NULL,
+    `filingDate`      date         DEFAULT NULL,
+    `amendedDate`     date         DEFAULT NULL,
+    `priceFrom`       float        DEFAULT NULL,
+    `priceTo`         float        DEFAULT NULL,
+    `offerPrice`      float        DEFAULT NULL,
+    `shares`          bigint       DEFAULT NULL,
+    `dealType`        varchar(16)  DEFAULT NULL,
+    `updatedAt`       timestamp    NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    UNIQUE KEY `uniq_code_exchange_startDate` (`code`, `exchange`, `startDate`),
+    KEY `idx_startDate` (`startDate`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `SplitCalendar`
+(
+    `code`       varchar(12) NOT NULL,
+    `exchange`   varchar(10) NOT NULL,
+    `splitDate`  date        NOT NULL,
+    `optionable` char(1)     DEFAULT NULL,
+    `oldShares`  float       DEFAULT NULL,
+    `newShares`  float       DEFAULT NULL,
+    `updatedAt`  timestamp   NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (`code`, `exchange`, `splitDate`),
+    KEY `idx_splitDate` (`splitDate`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `MacroIndicator`
+(
+    `countryISO3` char(3)      NOT NULL,
+    `indicator`   varchar(64)  NOT NULL,
+    `date`        date         NOT NULL,
+    `name`        varchar(128) DEFAULT NULL,
+    `period`      varchar(16)  DEFAULT NULL,
+    `value`       double       DEFAULT NULL,
+    PRIMARY KEY (`countryISO3`, `indicator`, `date`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `EconomicEvent`
+(
+    `countryISO3`      char(3)      NOT NULL,
+    `type`             varchar(128) NOT NULL,
+    `date`             datetime     NOT NULL,
+    `comparison`       varchar(8)   NOT NULL DEFAULT '',
+    `period`           varchar(32)  DEFAULT NULL,
+    `actual`           double       DEFAULT NULL,
+    `previous`         double       DEFAULT NULL,
+    `estimate`         double       DEFAULT NULL,
+    `change`           double       DEFAULT NULL,
+    `changePercentage` double       DEFAULT NULL,
+    PRIMARY KEY (`countryISO3`, `type`, `date`, `comparison`),
+    KEY `idx_date` (`date`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `Technicals`
+(
+    `code`      varchar(12) NOT NULL,
+    `exchange`  varchar(10) NOT NULL,
+    `date`      date        NOT NULL,
+    `indicator` varchar(32) NOT NULL,
+    `value`     double      DEFAULT NULL,
+    PRIMARY KEY (`code`, `exchange`, `date`, `indicator`),
+    KEY `idx_indicator_date` (`indicator`, `date`),
+    FOREIGN KEY (`code`) REFERENCES `ExchangeSymbol` (`code`),
+    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `SymbolChangeLog`
+(
+    `id`         int unsigned NOT NULL AUTO_INCREMENT,
+    `code`       varchar(12)  NOT NULL,
+    `exchange`   varchar(10)  NOT NULL,
+    `changeType` enum ('ADDED','REMOVED','CHANGED') NOT NULL,
+    `field`      varchar(16)  DEFAULT NULL,
+    `oldValue`   varchar(250) DEFAULT NULL,
+    `newValue`   varchar(250) DEFAULT NULL,
+    `changedAt`  timestamp    NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (`id`),
+    KEY `idx_code_exchange` (`code`, `exchange`),
+    KEY `idx_changedAt` (`changedAt`),
+    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `OptionContract`
+(
+    `contractName`   varchar(32) NOT NULL,
+    `code`           varchar(12) NOT NULL,
+    `exchange`       varchar(10) NOT NULL,
+    `type`           enum ('CALL','PUT') NOT NULL,
+    `strike`         double      NOT NULL,
+    `expirationDate` date        NOT NULL,
+    `contractSize`   varchar(16) DEFAULT NULL,
+    `currency`       char(3)     DEFAULT NULL,
+    PRIMARY KEY (`contractName`),
+    KEY `idx_underlying_expiration` (`code`, `exchange`, `expirationDate`),
+    FOREIGN KEY (`code`) REFERENCES `ExchangeSymbol` (`code`),
+    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `OptionSnapshot`
+(
+    `contractName`      varchar(32) NOT NULL,
+    `date`              date        NOT NULL,
+    `lastPrice`         double      DEFAULT NULL,
+    `bid`               double      DEFAULT NULL,
+    `ask`               double      DEFAULT NULL,
+    `change`            double      DEFAULT NULL,
+    `changePercent`     double      DEFAULT NULL,
+    `volume`            int         DEFAULT NULL,
+    `openInterest`      int         DEFAULT NULL,
+    `impliedVolatility` double      DEFAULT NULL,
+    `delta`             double      DEFAULT NULL,
+    `gamma`             double      DEFAULT NULL,
+    `theta`             double      DEFAULT NULL,
+    `vega`              double      DEFAULT NULL,
+    `rho`               double      DEFAULT NULL,
+    `theoretical`       double      DEFAULT NULL,
+    PRIMARY KEY (`contractName`, `date`),
+    FOREIGN KEY (`contractName`) REFERENCES `OptionContract` (`contractName`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `BondFundamental`
+(
+    `isin`            char(12)     NOT NULL,
+    `cusip`           char(9)      DEFAULT NULL,
+    `name`            varchar(250) DEFAULT NULL,
+    `currency`        char(3)      DEFAULT NULL,
+    `type`            varchar(64)  DEFAULT NULL,
+    `coupon`          double       DEFAULT NULL,
+    `price`           double       DEFAULT NULL,
+    `yieldToMaturity` double       DEFAULT NULL,
+    `maturityDate`    date         DEFAULT NULL,
+    `callable`        varchar(8)   DEFAULT NULL,
+    `issueDate`       date         DEFAULT NULL,
+    `issuer`          varchar(250) DEFAULT NULL,
+    `issuerCountry`   varchar(64)  DEFAULT NULL,
+    `updatedAt`       timestamp    NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (`isin`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+-- Yields in percent, e.g. `US10Y` for the ten year US treasury
+CREATE TABLE `GovernmentBondYield`
+(
+    `code`  varchar(12) NOT NULL,
+    `date`  date        NOT NULL,
+    `open`  double      DEFAULT NULL,
+    `high`  double      DEFAULT NULL,
+    `low`   double      DEFAULT NULL,
+    `close` double      DEFAULT NULL,
+    PRIMARY KEY (`code`, `date`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `MarketCapHistory`
+(
+    `code`      varchar(12) NOT NULL,
+    `exchange`  varchar(10) NOT NULL,
+    `date`      date        NOT NULL,
+    `marketCap` double      DEFAULT NULL,
+    PRIMARY KEY (`code`, `exchange`, `date`),
+    FOREIGN KEY (`code`) REFERENCES `ExchangeSymbol` (`code`),
+    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `DailySentiment`
+(
+    `code`         varchar(12)  NOT NULL,
+    `exchange`     varchar(10)  NOT NULL,
+    `date`         date         NOT NULL,
+    `normalized`   double       NOT NULL,
+    `articleCount` int unsigned NOT NULL,
+    PRIMARY KEY (`code`, `exchange`, `date`),
+    FOREIGN KEY (`code`) REFERENCES `ExchangeSymbol` (`code`),
+    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+-- StockPrice rolled up into larger bars, aligned to the session open. gmtoffset is the exchange's UTC offset in seconds
+CREATE TABLE `StockPriceAggregate`
+(
+    `code`        varchar(12)              NOT NULL,
+    `exchange`    varchar(10)              NOT NULL,
+    `barInterval` ENUM ('15m', '1h', '1d') NOT NULL,
+    `timestamp`   timestamp                NOT NULL,
+    `gmtoffset`   int                      NOT NULL,
+    `open`        double                   NOT NULL,
+    `high`        double                   NOT NULL,
+    `low`         double                   NOT NULL,
+    `close`       double                   NOT NULL,
+    `volume`      bigint DEFAULT NULL,
+    PRIMARY KEY (`code`, `exchange`, `barInterval`, `timestamp`),
+    FOREIGN KEY (`code`) REFERENCES `ExchangeSymbol` (`code`),
+    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+-- Prices that broke a rule of the validator, moved out of StockPrice or StockPriceEOD
+CREATE TABLE `QuarantinedPrice`
+(
+    `id`             int                     NOT NULL AUTO_INCREMENT,
+    `code`           varchar(12)             NOT NULL,
+    `exchange`       varchar(10)             NOT NULL,
+    `source`         ENUM ('INTRADAY','EOD') NOT NULL,
+    `timestamp`      datetime                NOT NULL,
+    `gmtoffset`      int    DEFAULT NULL,
+    `open`           double DEFAULT NULL,
+    `high`           double DEFAULT NULL,
+    `low`            double DEFAULT NULL,
+    `close`          double DEFAULT NULL,
+    `adjusted_close` double DEFAULT NULL,
+    `volume`         bigint DEFAULT NULL,
+    `rule`           varchar(20)             NOT NULL,
+    `quarantinedAt`  timestamp DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (`id`),
+    KEY `idx_code_exchange` (`code`, `exchange`),
+    FOREIGN KEY (`code`) REFERENCES `ExchangeSymbol` (`code`),
+    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+-- Session hours from the exchange-details endpoint. The UTC hours are outside daylight saving time
+CREATE TABLE `ExchangeHours`
+(
+    `exchange`    varchar(10) NOT NULL,
+    `timezone`    varchar(40) DEFAULT NULL,
+    `open`        time        NOT NULL,
+    `close`       time        NOT NULL,
+    `openUTC`     time        NOT NULL,
+    `closeUTC`    time        NOT NULL,
+    `workingDays` varchar(30) NOT NULL,
+    `updatedAt`   timestamp   DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (`exchange`),
+    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+CREATE TABLE `ExchangeHoliday`
+(
+    `exchange` varchar(10)  NOT NULL,
+    `date`     date         NOT NULL,
+    `name`     varchar(100) NOT NULL,
+    PRIMARY KEY (`exchange`, `date`),
+    FOREIGN KEY (`exchange`) REFERENCES `Exchange` (`code`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
+
+-- The retention `partitions` last ran with, so syncs don't fetch the months it removes.
+-- `exchange` is empty for --default-retention, hence no foreign key
+CREATE TABLE `PriceRetention`
+(
+    `exchange`  varchar(10) NOT NULL,
+    `months`    int unsigned NOT NULL,
+    `updatedAt` timestamp DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (`exchange`)
+) ENGINE = InnoDB
+  DEFAULT CHARSET = utf8mb4
+  COLLATE = utf8mb4_0900_ai_ci;
@@ -0,0 +1,362 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use sqlx::FromRow;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::trading_calendar::TradingCalendar;
+
+/// A stored intraday bar. Like `models::Intraday`, but read back from `StockPrice`
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct Bar {
+    pub timestamp: DateTime<Utc>,
+    /// Seconds to add to `timestamp` for the exchange's local time. What EODHD sent for a stored bar,
+    /// which is often 0, and what the calendar says for a resampled one
+    #[sqlx(rename = "gmtoffset")]
+    pub gmt_offset: i32,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: Option<i64>,
+}
+
+/// A stored daily bar from `StockPriceEOD`
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct EodBar {
+    pub date: NaiveDate,
+    pub open: Option<f64>,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub close: Option<f64>,
+    pub adjusted_close: Option<f64>,
+    pub volume: Option<i64>,
+}
+
+/**
+ * How much earlier than the start of its interval a bar can be. A daily bar is stamped with the
+ * session open but holds the pre-market before it.
+ */
+pub const BUCKET_REACH: TimeDelta = TimeDelta::days(1);
+
+/// What `StockPriceAggregate` is kept up to date with when no intervals are asked for
+pub const DEFAULT_INTERVALS: &[BarInterval] = &[
+    BarInterval::Minutes15,
+    BarInterval::Hour1,
+    BarInterval::Day1,
+];
+
+/// Bar sizes `StockPrice` can be read in. Parsed from `5m`, `15m`, `1h` or `1d`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BarInterval {
+    /// What `StockPrice` stores
+    Minutes5,
+    Minutes15,
+    Hour1,
+    Day1,
+}
+
+impl BarInterval {
+    pub fn seconds(&self) -> i64 {
+        match self {
+            Self::Minutes5 => 5 * 60,
+            Self::Minutes15 => 15 * 60,
+            Self::Hour1 => 60 * 60,
+            Self::Day1 => 24 * 60 * 60,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Minutes5 => "5m",
+            Self::Minutes15 => "15m",
+            Self::Hour1 => "1h",
+            Self::Day1 => "1d",
+        }
+    }
+
+    /**
+     * Start of the interval `timestamp` falls into. Intervals are aligned to the session open of the
+     * local day, so the first hourly bar starts at the open and a daily bar, stamped with the open,
+     * holds the whole local day.
+     */
+    pub fn bucket_start(
+        &self,
+        timestamp: DateTime<Utc>,
+        calendar: &TradingCalendar,
+    ) -> DateTime<Utc> {
+        let (open, _) = calendar.session_bounds(calendar.local_date(timestamp));
+        match self {
+            Self::Day1 => open,
+            _ => {
+                let since_open = (timestamp - open).num_seconds();
+                open + TimeDelta::seconds(since_open - since_open.rem_euclid(self.seconds()))
+            }
+        }
+    }
+}
+
+impl FromStr for BarInterval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "5m" => Ok(Self::Minutes5),
+            "15m" => Ok(Self::Minutes15),
+            "1h" => Ok(Self::Hour1),
+            "1d" => Ok(Self::Day1),
+            _ => bail!("Unknown bar interval '{s}'. Expected 5m, 15m, 1h or 1d"),
+        }
+    }
+}
+
+impl Display for BarInterval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Folds `bar` into `into`, which must be the earlier bar of the same interval
+fn merge(into: &mut Bar, bar: Bar) {
+    into.high = into.high.max(bar.high);
+    into.low = into.low.min(bar.low);
+    into.close = bar.close;
+    into.volume = match (into.volume, bar.volume) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+    };
+}
+
+/**
+ * Rolls a stream of bars, oldest first, up into `interval` bars stamped with the start of their
+ * interval in `calendar`. Open is the first bar's, close the last one's and volume the sum.
+ */
+pub fn resample<'a, E: Send + 'a>(
+    bars: BoxStream<'a, Result<Bar, E>>,
+    interval: BarInterval,
+    calendar: &'a TradingCalendar,
+) -> BoxStream<'a, Result<Bar, E>> {
+    if interval == BarInterval::Minutes5 {
+        return bars;
+    }
+
+    stream::unfold(
+        (bars, None::<Bar>, false),
+        move |(mut bars, mut pending, done)| async move {
+            if done {
+                return None;
+            }
+            loop {
+                match bars.next().await {
+                    Some(Ok(bar)) => {
+                        let start = interval.bucket_start(bar.timestamp, calendar);
+                        match pending.as_mut() {
+                            Some(current) if current.timestamp == start => merge(current, bar),
+                            _ => {
+                                let gmt_offset = calendar.utc_offset(start.date_naive());
+                                let finished = pending.replace(Bar {
+                                    timestamp: start,
+                                    gmt_offset: gmt_offset.num_seconds() as i32,
+                                    ..bar
+                                });
+                                if let Some(finished) = finished {
+                                    return Some((Ok(finished), (bars, pending, false)));
+                                }
+                            }
+                        }
+                    }
+                    Some(Err(e)) => return Some((Err(e), (bars, None, true))),
+                    None => return pending.map(|bar| (Ok(bar), (bars, None, true))),
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+/**
+ * The resampled `bars` whose interval starts from `from` to `to`, both inclusive. For the first and
+ * last of them to be whole, `bars` has to be read from `BUCKET_REACH` before `from` and until a day after `to`.
+ */
+pub fn buckets_within<'a, E: Send + 'a>(
+    bars: BoxStream<'a, Result<Bar, E>>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> BoxStream<'a, Result<Bar, E>> {
+    bars.filter(move |bar| {
+        let within = bar
+            .as_ref()
+            .map_or(true, |bar| (from..=to).contains(&bar.timestamp));
+        std::future::ready(within)
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+    use std::collections::BTreeMap;
+
+    fn at(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(2024, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    /// 5 minute bars from `from`, closing at 1, 2, 3 and so on
+    fn bars(from: DateTime<Utc>, count: usize) -> Vec<Bar> {
+        (0..count)
+            .map(|i| {
+                let close = (i + 1) as f64;
+                Bar {
+                    timestamp: from + TimeDelta::minutes(5 * i as i64),
+                    gmt_offset: 0,
+                    open: close - 0.5,
+                    high: close + 1.0,
+                    low: close - 1.0,
+                    close,
+                    volume: Some(10),
+                }
+            })
+            .collect()
+    }
+
+    async fn resampled(
+        bars: Vec<Bar>,
+        interval: BarInterval,
+        calendar: &TradingCalendar,
+    ) -> Vec<Bar> {
+        let bars = stream::iter(bars.into_iter().map(Ok::<_, ()>)).boxed();
+        resample(bars, interval, calendar)
+            .map(Result::unwrap)
+            .collect()
+            .await
+    }
+
+    /// Like ASX during Australian summer, which opens the evening before in UTC
+    fn sydney() -> TradingCalendar {
+        TradingCalendar {
+            open: NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+            close: NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+            open_utc: NaiveTime::from_hms_opt(23, 0, 0).unwrap(),
+            close_utc: NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+            ..TradingCalendar::fallback("AU")
+        }
+    }
+
+    #[tokio::test]
+    async fn hours_start_at_the_open() {
+        let us = TradingCalendar::fallback("US");
+
+        let summer = resampled(bars(at(7, 1, 13, 30), 78), BarInterval::Hour1, &us).await;
+        assert_eq!(summer.len(), 7);
+        assert_eq!(summer[0].timestamp, at(7, 1, 13, 30));
+        assert_eq!(summer[6].timestamp, at(7, 1, 19, 30));
+        assert_eq!(summer[0].gmt_offset, -4 * 60 * 60);
+
+        let winter = resampled(bars(at(1, 2, 14, 30), 78), BarInterval::Hour1, &us).await;
+        assert_eq!(winter[0].timestamp, at(1, 2, 14, 30));
+        assert_eq!(winter[0].gmt_offset, -5 * 60 * 60);
+    }
+
+    #[tokio::test]
+    async fn bars_are_rolled_up() {
+        let us = TradingCalendar::fallback("US");
+        let mut input = bars(at(7, 1, 13, 30), 6);
+        input[4].volume = None;
+
+        let quarters = resampled(input, BarInterval::Minutes15, &us).await;
+        let first = Bar {
+            timestamp: at(7, 1, 13, 30),
+            gmt_offset: -4 * 60 * 60,
+            open: 0.5,
+            high: 4.0,
+            low: 0.0,
+            close: 3.0,
+            volume: Some(30),
+        };
+        let second = Bar {
+            timestamp: at(7, 1, 13, 45),
+            open: 3.5,
+            high: 7.0,
+            low: 3.0,
+            close: 6.0,
+            volume: Some(20),
+            ..first
+        };
+        assert_eq!(quarters, [first, second]);
+
+        let unchanged = bars(at(7, 1, 13, 30), 3);
+        assert_eq!(
+            resampled(unchanged.clone(), BarInterval::Minutes5, &us).await,
+            unchanged
+        );
+    }
+
+    #[tokio::test]
+    async fn days_hold_the_local_day() {
+        let us = TradingCalendar::fallback("US");
+        // Pre market, the session and after hours of one day
+        let mut input = bars(at(7, 1, 12, 0), 2);
+        input.extend(bars(at(7, 1, 13, 30), 78));
+        input.extend(bars(at(7, 1, 22, 0), 2));
+        input.extend(bars(at(7, 2, 13, 30), 1));
+
+        let days = resampled(input, BarInterval::Day1, &us).await;
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].timestamp, at(7, 1, 13, 30));
+        assert_eq!(days[0].volume, Some(82 * 10));
+        assert_eq!(days[1].timestamp, at(7, 2, 13, 30));
+
+        // A session crossing midnight UTC is still a single day
+        let days = resampled(bars(at(7, 7, 23, 0), 72), BarInterval::Day1, &sydney()).await;
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].timestamp, at(7, 7, 23, 0));
+        assert_eq!(days[0].gmt_offset, 11 * 60 * 60);
+    }
+
+    #[tokio::test]
+    async fn incremental_runs_match_a_rebuild() {
+        let us = TradingCalendar::fallback("US");
+        let mut first_run = bars(at(7, 1, 12, 0), 4);
+        first_run.extend(bars(at(7, 1, 13, 30), 78));
+        first_run.extend(bars(at(7, 2, 12, 0), 4));
+        first_run.extend(bars(at(7, 2, 13, 30), 6));
+        let mut second_run = bars(at(7, 2, 14, 0), 66);
+        second_run.extend(bars(at(7, 2, 22, 0), 3));
+        let all = [first_run.clone(), second_run].concat();
+
+        for interval in DEFAULT_INTERVALS.iter().copied() {
+            let mut stored = resampled(first_run.clone(), interval, &us)
+                .await
+                .into_iter()
+                .map(|bar| (bar.timestamp, bar))
+                .collect::<BTreeMap<_, _>>();
+
+            // Like `aggregate_bars`, starting over at the latest aggregate
+            let latest = *stored.keys().last().unwrap();
+            let read = all
+                .iter()
+                .filter(|bar| bar.timestamp >= latest - BUCKET_REACH)
+                .cloned()
+                .map(Ok::<_, ()>);
+            let read = resample(stream::iter(read).boxed(), interval, &us);
+            let mut updated = buckets_within(read, latest, at(12, 31, 0, 0));
+            while let Some(bar) = updated.next().await {
+                let bar = bar.unwrap();
+                stored.insert(bar.timestamp, bar);
+            }
+
+            let rebuilt = resampled(all.clone(), interval, &us).await;
+            assert_eq!(
+                stored.into_values().collect::<Vec<_>>(),
+                rebuilt,
+                "{interval}"
+            );
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::{bail, Result};
 use async_trait::async_trait;
+use chrono::NaiveDate;
 use serde::{de::DeserializeOwned, Serialize};
 use std::{os::unix::fs::MetadataExt, path::Path, sync::Arc};
 use tokio::{
@@ -8,8 +9,9 @@ use tokio::{
     sync::Mutex,
 };
 
-pub const DOWNLOADED_FILE_NAME: &'static str = "downloaded.json";
-pub const FAILED_FILE_NAME: &'static str = "failed.json";
+pub const DOWNLOADED_FILE_NAME: &str = "downloaded.json";
+pub const FAILED_FILE_NAME: &str = "failed.json";
+const REMOVED_FAILS_FILE_NAME: &str = "last_fails_which_are_removed.json";
 
 #[async_trait]
 pub trait Config<S>
@@ -29,6 +31,90 @@ where
 {
     pub downloaded: Arc<Mutex<Vec<S>>>,
     pub failed: Arc<Mutex<Vec<S>>>,
+    /// Keeps the state files of different routines apart, e.g. `downloaded-options.json`
+    name: Option<Box<str>>,
+}
+
+impl<S> SyncedConfig<S>
+where
+    S: DeserializeOwned + Serialize + Send + Sync + 'static + Clone,
+{
+    /// Like `Config::load` but with its own set of state files
+    pub async fn load_named(name: &str) -> Self {
+        Self::load_from(Some(name.into())).await
+    }
+
+    /**
+     * State that only lasts for `day`, named `{routine}-{exchange}-{day}`. What earlier days
+     * left behind is removed, so an interrupted run resumes while the next day starts over.
+     */
+    pub async fn load_daily(routine: &str, exchange: &str, day: NaiveDate) -> Self {
+        let prefix = format!("{routine}-{exchange}-");
+        if let Err(e) = remove_earlier_days(&prefix, day).await {
+            eprintln!("Failed to remove old '{prefix}' state files: {e:?}");
+        }
+        Self::load_named(&format!("{prefix}{day}")).await
+    }
+
+    async fn load_from(name: Option<Box<str>>) -> Self {
+        let (downloaded_file, failed_file) = (
+            state_file_name(&name, DOWNLOADED_FILE_NAME),
+            state_file_name(&name, FAILED_FILE_NAME),
+        );
+        let (downloaded, failed) = (
+            load_serializable(downloaded_file),
+            load_serializable(failed_file),
+        );
+        let (downloaded, failed) = tokio::join!(downloaded, failed);
+        let downloaded = Arc::new(Mutex::new(downloaded.unwrap_or_default()));
+        let failed = Arc::new(Mutex::new(failed.unwrap_or_default()));
+
+        Self {
+            downloaded,
+            failed,
+            name,
+        }
+    }
+}
+
+/// Removes the state files named `{prefix}{date}` for dates before `day`
+async fn remove_earlier_days(prefix: &str, day: NaiveDate) -> std::io::Result<()> {
+    let stems = [
+        DOWNLOADED_FILE_NAME,
+        FAILED_FILE_NAME,
+        REMOVED_FAILS_FILE_NAME,
+    ]
+    .map(|x| x.trim_end_matches(".json"));
+    let mut entries = tokio::fs::read_dir(".").await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str().and_then(|x| x.strip_suffix(".json")) else {
+            continue;
+        };
+        let is_earlier = stems
+            .iter()
+            .filter_map(|stem| {
+                name.strip_prefix(stem)?
+                    .strip_prefix('-')?
+                    .strip_prefix(prefix)
+            })
+            .filter_map(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+            .any(|date| date < day);
+        if is_earlier {
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+fn state_file_name(name: &Option<Box<str>>, file_name: &str) -> String {
+    match name {
+        Some(name) => {
+            let (stem, extension) = file_name.rsplit_once('.').unwrap_or((file_name, "json"));
+            format!("./{stem}-{name}.{extension}")
+        }
+        None => format!("./{file_name}"),
+    }
 }
 
 #[async_trait]
@@ -42,15 +128,23 @@ where
         let failed = if let Some(not_the_last) = not_the_last {
             let (failed, removed_fails) =
                 failed.split_at(failed.len().saturating_sub(not_the_last));
-            save_serializable_iter("./last_fails_which_are_removed.json", removed_fails).await.expect("Failed to save removed fails");
+            save_serializable_iter(
+                state_file_name(&self.name, REMOVED_FAILS_FILE_NAME),
+                removed_fails,
+            )
+            .await
+            .expect("Failed to save removed fails");
             failed
         } else {
             failed.as_ref()
         };
 
         let (status_downloaded, status_failed) = (
-            save_serializable_iter("./downloaded.json", downloaded.as_ref()),
-            save_serializable_iter("./failed.json", failed),
+            save_serializable_iter(
+                state_file_name(&self.name, DOWNLOADED_FILE_NAME),
+                downloaded.as_ref(),
+            ),
+            save_serializable_iter(state_file_name(&self.name, FAILED_FILE_NAME), failed),
         );
         let (status_downloaded, status_failed) = tokio::join!(status_downloaded, status_failed);
         if let Err(err_downloaded) = status_downloaded {
@@ -68,15 +162,7 @@ where
     }
 
     async fn load() -> Self {
-        let (downloaded, failed) = (
-            load_serializable("./downloaded.json"),
-            load_serializable("./failed.json"),
-        );
-        let (downloaded, failed) = tokio::join!(downloaded, failed);
-        let downloaded = Arc::new(Mutex::new(downloaded.unwrap_or_default()));
-        let failed = Arc::new(Mutex::new(failed.unwrap_or_default()));
-
-        Self { downloaded, failed }
+        Self::load_from(None).await
     }
 
     async fn get_filter(&self) -> Vec<S> {
@@ -1,23 +1,111 @@
-use anyhow::Result;
-use colored::Colorize;
-use futures::Future;
-use serde::Serialize;
-use std::collections::HashSet;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Months, NaiveDate, TimeDelta, Utc};
+use colored::{ColoredString, Colorize};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::{Future, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::signal;
-use tokio::sync::{Mutex, Semaphore};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
 
+use crate::bars::{buckets_within, BarInterval, BUCKET_REACH, DEFAULT_INTERVALS};
 use crate::config::{load_serializable, save_serializable_generic, Config, SyncedConfig};
-use crate::models::ExchangeSymbol;
+use crate::gaps::find_gaps;
+use crate::models::{split_code, AssetClass, ExchangeSymbol, IdKind, IdMapping, ScreenerFilters};
+use crate::partitions::{
+    first_of_month, is_expired, months_between, next_month, partition_month, Retention,
+};
+use crate::storage::Storage;
+use crate::technicals::{Indicator, PriceSource, DEFAULT_INDICATORS};
+use crate::trading_calendar::TradingCalendar;
+use crate::validate::{PriceTable, Rule, Validator};
 use crate::{db::Db, eodhd::Eodhd};
 
+const CALENDAR_DAYS_AHEAD: TimeDelta = TimeDelta::days(90);
+const INSIDER_BACKFILL: TimeDelta = TimeDelta::days(365);
+/// Stored indicators this close to the latest one are rewritten, so revised closes (splits) show up
+const TECHNICALS_REFRESH: TimeDelta = TimeDelta::days(30);
+const OPTIONS_MAX_EXPIRY: TimeDelta = TimeDelta::days(2 * 365);
+const GOVERNMENT_BOND_EXCHANGE: &str = "GBOND";
+/// API calls per request, as EODHD bills them
+const INTRADAY_CALL_COST: usize = 5;
+const OPTIONS_CALL_COST: usize = 10;
+const MARKET_CAP_CALL_COST: usize = 10;
+const SENTIMENT_CALL_COST: usize = 5;
+/// Aggregated bars are written in batches of this many
+const AGGREGATE_BATCH: usize = 1000;
+/// Rows of a symbol's stored prices checked at once
+const VALIDATE_BATCH: usize = 10_000;
+/// Rows deleted per statement when only some exchanges of a partition are past their retention
+const RETENTION_DELETE_BATCH: u64 = 10_000;
+
+/// The parts of a dump that can be selected from the command line. The names match `StageDone.stage`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Intraday,
+    Calendar,
+    Insider,
+    Options,
+    Bonds,
+    MarketCap,
+    Sentiment,
+    Aggregate,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Intraday => "INTRADAY",
+            Self::Calendar => "CALENDAR",
+            Self::Insider => "INSIDER",
+            Self::Options => "OPTIONS",
+            Self::Bonds => "BONDS",
+            Self::MarketCap => "MARKET_CAP",
+            Self::Sentiment => "SENTIMENT",
+            Self::Aggregate => "AGGREGATE",
+        }
+    }
+}
+
+impl FromStr for Stage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_uppercase().as_str() {
+            "INTRADAY" => Ok(Self::Intraday),
+            "CALENDAR" => Ok(Self::Calendar),
+            "INSIDER" => Ok(Self::Insider),
+            "OPTIONS" => Ok(Self::Options),
+            "BONDS" => Ok(Self::Bonds),
+            "MARKET_CAP" | "MARKET-CAP" => Ok(Self::MarketCap),
+            "SENTIMENT" => Ok(Self::Sentiment),
+            "AGGREGATE" => Ok(Self::Aggregate),
+            _ => bail!("Unknown stage '{s}'"),
+        }
+    }
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 pub async fn dump<T, Ex>(
     exchange_short_code: Ex,
     eodhd: Eodhd<T>,
-    db: Db,
+    db: Arc<dyn Storage>,
     threads: usize,
+    stages: &[Stage],
+    universe: Option<Vec<Box<str>>>,
+    bond_isins: &[String],
 ) -> Result<()>
 where
     T: Display + Send + Sync + 'static + Serialize,
@@ -25,14 +113,26 @@ where
 {
     let (dump_txt, error_txt) = ("DUMP".bold().magenta(), "ERROR".red());
     println!("[{}] Starting dump", &dump_txt);
-    let state_file = "has-finished-prices.json";
-    let (eodhd, db) = (Arc::new(eodhd), Arc::new(db));
+    let exchange_short_code = exchange_short_code.to_string();
+    let state_file = prices_state_file(&exchange_short_code);
+    let eodhd = Arc::new(eodhd);
 
-    let has_finished_prices: bool = load_serializable(state_file).await.unwrap_or_default();
+    // Fail now rather than after hours of prices
+    check_stages(&db, stages)?;
 
-    if !has_finished_prices {
-        match dump_prices(&exchange_short_code, eodhd.clone(), db.clone(), threads).await? {
-            ExitedPrematurly::YES => {
+    let has_finished_prices: bool = load_serializable(&state_file).await.unwrap_or_default();
+
+    if stages.contains(&Stage::Intraday) && !has_finished_prices {
+        match dump_prices(
+            &exchange_short_code,
+            eodhd.clone(),
+            db.clone(),
+            threads,
+            universe,
+        )
+        .await?
+        {
+            ExitedPrematurly::Yes => {
                 println!(
                     "[{}] Exited prematurly from dumping prices. Will shut down now",
                     &dump_txt
@@ -40,280 +140,2100 @@ where
                 return Ok(());
             }
 
-            ExitedPrematurly::NO => {
+            ExitedPrematurly::No => {
                 println!(
                     "[{}] Everything went well with dumping prices. Will proceed to fundamental",
                     &dump_txt
                 );
-                if let Err(e) = save_serializable_generic(state_file, true).await {
+                if let Err(e) = save_serializable_generic(&state_file, true).await {
                     eprintln!("[{}] ({}) Failed to write to '{state_file}'. Will pass on error now. Please remember that we has finished prices",&dump_txt, &error_txt);
                     return Err(e);
                 };
+                db.add_stage(&exchange_short_code, Stage::Intraday.as_str())
+                    .await?;
             }
         }
     }
 
-    Ok(())
-}
-
-async fn dump_prices<T, Ex>(
-    exchange_short_code: Ex,
-    eodhd: Arc<Eodhd<T>>,
-    db: Arc<Db>,
-    threads: usize,
-) -> Result<ExitedPrematurly>
-where
-    T: Display + Send + Sync + 'static + Serialize,
-    Ex: Display,
-{
-    let (dump_prices_txt, error_txt) = (
-        Arc::new("DUMP PRICES".bold().purple()),
-        Arc::new("ERROR".red()),
-    );
-    let max_errors_in_row: usize = threads * 2;
-
-    let config = Arc::new(SyncedConfig::<Arc<str>>::load().await);
-    let filter_content = config.get_filter().await;
-    let (ctrl_c_handler, cancellation_token) = {
-        let local_config = config.clone();
+    if stages.contains(&Stage::Calendar) {
+        let mysql = require_mysql(&db, Stage::Calendar)?;
+        dump_calendar(&exchange_short_code, &eodhd, &mysql).await?;
+        db.add_stage(&exchange_short_code, Stage::Calendar.as_str())
+            .await?;
+    }
 
-        let a = async move {
-            local_config
-                .save(None)
-                .await
-                .expect("Failed to save config");
-        };
-        get_ctrl_c_handler(a)
-    };
+    if stages.contains(&Stage::Insider) {
+        let mysql = require_mysql(&db, Stage::Insider)?;
+        dump_insider_transactions(&exchange_short_code, &eodhd, &mysql).await?;
+        db.add_stage(&exchange_short_code, Stage::Insider.as_str())
+            .await?;
+    }
 
-    if filter_content.is_empty() {
-        println!("[{}] Filter files was empty", &dump_prices_txt);
+    if stages.contains(&Stage::Options) {
+        let mysql = require_mysql(&db, Stage::Options)?;
+        match dump_options(&exchange_short_code, eodhd.clone(), mysql, threads).await? {
+            ExitedPrematurly::Yes => {
+                println!(
+                    "[{}] Exited prematurly from dumping options. Will shut down now",
+                    &dump_txt
+                );
+                return Ok(());
+            }
+            ExitedPrematurly::No => {
+                db.add_stage(&exchange_short_code, Stage::Options.as_str())
+                    .await?;
+            }
+        }
     }
-    let filter: HashSet<&str> = HashSet::from_iter(filter_content.iter().map(|x| x.as_ref()));
-    let symbols = eodhd.get_exchange_symbols(exchange_short_code).await?;
-    let symbols = symbols.into_iter().filter(|symbol| {
-        let s = format!("{}.{}", symbol.code, symbol.exchange);
-        !filter.contains(s.as_str())
-    });
 
-    let semaphore = Arc::new(Semaphore::new(threads));
-    let errors_in_row = Arc::new(Mutex::new(0 as usize));
-    let mut handles = Vec::new();
+    if stages.contains(&Stage::Bonds) {
+        let mysql = require_mysql(&db, Stage::Bonds)?;
+        dump_bonds(bond_isins, &eodhd, &mysql).await?;
+        db.add_stage(&exchange_short_code, Stage::Bonds.as_str())
+            .await?;
+    }
 
-    for symbol in symbols {
-        {
-            let errors_lock = errors_in_row.lock().await;
-            if *errors_lock > max_errors_in_row {
-                eprintln!(
-                    "[{}] We have failed {} times in a row. Exiting now...",
-                    &dump_prices_txt, max_errors_in_row
+    if stages.contains(&Stage::MarketCap) {
+        let mysql = require_mysql(&db, Stage::MarketCap)?;
+        match dump_market_caps(&exchange_short_code, eodhd.clone(), mysql, threads).await? {
+            ExitedPrematurly::Yes => {
+                println!(
+                    "[{}] Exited prematurly from dumping market caps. Will shut down now",
+                    &dump_txt
                 );
-                config.save(None).await.expect("Failed to save config");
-                return Ok(ExitedPrematurly::YES);
+                return Ok(());
             }
-            if cancellation_token.load(Ordering::SeqCst) {
-                eprintln!("[{}] Ctrl+C is pressed. Exiting...", &dump_prices_txt);
-                return Ok(ExitedPrematurly::YES);
+            ExitedPrematurly::No => {
+                db.add_stage(&exchange_short_code, Stage::MarketCap.as_str())
+                    .await?;
             }
         }
+    }
 
-        let code_exchange = format!("{}.{}", symbol.code.as_ref(), symbol.exchange.as_ref());
-        let permit = semaphore.clone().acquire_owned().await.unwrap();
-        let db = db.clone();
-        let errors_in_row = errors_in_row.clone();
-        let eodhd = eodhd.clone();
-        let config = config.clone();
-        let (dump_prices_txt, error_txt) = (dump_prices_txt.clone(), error_txt.clone());
-        let handle = tokio::spawn(async move {
-            if let Err(e) = process_symbol(permit, dump_prices_txt.clone(), eodhd, symbol, db).await
-            {
-                let errors = {
-                    let mut errors_lock = errors_in_row.lock().await;
-                    (*errors_lock) += 1;
-                    *errors_lock
-                };
-                eprintln!(
-                    "[{}] ({}) ({}/{}) Failed to download/push {code_exchange} with error: {:?}",
-                    &dump_prices_txt, &error_txt, errors, max_errors_in_row, &e
+    if stages.contains(&Stage::Sentiment) {
+        let mysql = require_mysql(&db, Stage::Sentiment)?;
+        match dump_sentiments(&exchange_short_code, eodhd.clone(), mysql, threads).await? {
+            ExitedPrematurly::Yes => {
+                println!(
+                    "[{}] Exited prematurly from dumping sentiment. Will shut down now",
+                    &dump_txt
                 );
-                config.append_failure(code_exchange.into()).await;
-            } else {
-                config.append_download(code_exchange.into()).await;
+                return Ok(());
             }
-        });
-        handles.push(handle);
+            ExitedPrematurly::No => {
+                db.add_stage(&exchange_short_code, Stage::Sentiment.as_str())
+                    .await?;
+            }
+        }
     }
 
-    config
-        .save(Some(*errors_in_row.lock().await))
-        .await
-        .expect("Failed to save config/state");
+    if stages.contains(&Stage::Aggregate) {
+        let mysql = require_mysql(&db, Stage::Aggregate)?;
+        aggregate_bars(
+            &exchange_short_code,
+            Vec::<Box<str>>::new(),
+            DEFAULT_INTERVALS.to_vec(),
+            false,
+            &mysql,
+        )
+        .await?;
+        db.add_stage(&exchange_short_code, Stage::Aggregate.as_str())
+            .await?;
+    }
 
-    let dump_prices_txt = "DUMP PRICES".bold().blue();
-    println!("[{}] Waiting for all tasks to finish...", &dump_prices_txt);
-    for handle in handles.into_iter() {
-        if let Err(e) = handle.await {
-            eprintln!(
-                "[{}] ({}) Encountered while awaiting all handles {:?}",
-                &dump_prices_txt, &error_txt, &e
-            );
-        }
+    Ok(())
+}
+
+/// Everything but prices, listings and bookkeeping is only implemented for MySQL
+fn require_mysql(db: &Arc<dyn Storage>, what: impl Display) -> Result<Arc<Db>> {
+    db.clone().mysql().ok_or_else(|| {
+        anyhow!("{what} needs a MySQL database. Postgres and SQLite only back the intraday stage and selective")
+    })
+}
+
+/// Refuses the stages `db` can't run, before any of them has started
+pub fn check_stages(db: &Arc<dyn Storage>, stages: &[Stage]) -> Result<()> {
+    for stage in stages.iter().filter(|x| **x != Stage::Intraday) {
+        require_mysql(db, stage)?;
     }
-    println!("[{}] Aborting ctrl-c handler...", &dump_prices_txt);
-    ctrl_c_handler.abort();
+    Ok(())
+}
 
-    Ok(ExitedPrematurly::NO)
+/// When an exchange trades, and how far back its intraday prices are fetched
+struct IntradayScope {
+    calendar: TradingCalendar,
+    /// See `retention_horizon`
+    max_from_date: Option<DateTime<Utc>>,
+    /// Symbols with recent prices, see `get_outdated_symbol_prices`
+    up_to_date: HashSet<Box<str>>,
+    /// The latest bar stored for each outdated symbol, where fetching picks up
+    last_stored: HashMap<Box<str>, DateTime<Utc>>,
 }
 
-fn get_ctrl_c_handler<F>(save: F) -> (tokio::task::JoinHandle<()>, Arc<AtomicBool>)
-where
-    F: Future<Output = ()> + Send + 'static,
-{
-    let break_switch = Arc::new(AtomicBool::new(false));
-    let break_switch_c = break_switch.clone();
-    let handle = tokio::spawn(async move {
-        signal::ctrl_c().await.expect("Failed to listen for ctrl_c");
-        let ctrl_c_txt = "CTRL C HANDLER".blue().bold();
-        println!("[{}] Ctrl+C received! Saving state", &ctrl_c_txt,);
-        save.await;
-        break_switch_c.store(true, Ordering::SeqCst);
-    });
-    (handle, break_switch)
+impl IntradayScope {
+    async fn load(exchange_short_code: &str, db: &Arc<dyn Storage>) -> Result<Self> {
+        let outdated = db
+            .get_outdated_symbol_prices(exchange_short_code)
+            .await?
+            .into_iter()
+            .map(|x| (x.code, x.last_updated))
+            .collect::<HashMap<_, _>>();
+        let up_to_date = db
+            .get_exchange_symbol_codes(exchange_short_code)
+            .await?
+            .into_iter()
+            .filter(|code| !outdated.contains_key(code))
+            .collect();
+
+        Ok(Self {
+            calendar: load_trading_calendar(exchange_short_code, db).await?,
+            max_from_date: retention_horizon(exchange_short_code, db).await?,
+            up_to_date,
+            last_stored: outdated
+                .into_iter()
+                .filter_map(|(code, last)| Some((code, last?.and_utc())))
+                .collect(),
+        })
+    }
+
+    /// Symbols not in `ExchangeSymbol` yet have never been fetched
+    fn is_outdated(&self, code: &str) -> bool {
+        !self.up_to_date.contains(code)
+    }
+
+    /// Where fetching `code` starts, as late as the retention and its stored bars allow
+    fn start_of(&self, code: &str) -> Option<DateTime<Utc>> {
+        self.max_from_date.max(self.last_stored.get(code).copied())
+    }
 }
-enum ExitedPrematurly {
-    YES,
-    NO,
+
+/// The stored calendar of the exchange if there is one, or else the built in one
+async fn load_trading_calendar(
+    exchange_short_code: &str,
+    db: &Arc<dyn Storage>,
+) -> Result<TradingCalendar> {
+    let stored = match db.clone().mysql() {
+        Some(mysql) => mysql.get_trading_calendar(exchange_short_code).await?,
+        None => None,
+    };
+    Ok(stored.unwrap_or_else(|| TradingCalendar::fallback(exchange_short_code)))
+}
+
+/**
+ * The start of the oldest month `partitions` keeps for the exchange. Intraday syncs don't go further
+ * back, or they would put back what retention just archived and removed.
+ */
+async fn retention_horizon(
+    exchange_short_code: &str,
+    db: &Arc<dyn Storage>,
+) -> Result<Option<DateTime<Utc>>> {
+    let months = match db.clone().mysql() {
+        Some(mysql) => mysql.get_retention(exchange_short_code).await?,
+        None => None,
+    };
+    let this_month = first_of_month(Utc::now().date_naive());
+    Ok(months.map(|months| {
+        (this_month - Months::new(months))
+            .and_time(Default::default())
+            .and_utc()
+    }))
 }
-impl Default for ExitedPrematurly {
-    fn default() -> Self {
-        Self::NO
+
+/// Which symbols the intraday stage has downloaded or failed, kept so an interrupted dump resumes
+async fn prices_config(exchange_short_code: &str) -> SyncedConfig<Arc<str>> {
+    // US keeps the unnamed state files it has always used
+    match exchange_short_code {
+        "US" => SyncedConfig::<Arc<str>>::load().await,
+        exchange => SyncedConfig::load_named(&format!("prices-{exchange}")).await,
     }
 }
 
-async fn process_symbol<T, D>(
-    _permit: tokio::sync::OwnedSemaphorePermit,
-    dump_prices_txt: D,
-    eodhd: Arc<Eodhd<T>>,
-    symbol: ExchangeSymbol,
-    db: Arc<Db>,
+fn prices_state_file(exchange_short_code: &str) -> String {
+    match exchange_short_code {
+        "US" => "has-finished-prices.json".to_string(),
+        exchange => format!("has-finished-prices-{exchange}.json"),
+    }
+}
+
+/**
+ * Prints the plan behind the API token and how much of today's limit is left. Fails if that
+ * isn't enough for an upper bound of what `stages` still have to fetch, given what is stored and
+ * what an interrupted dump left to resume. `universe` are the codes the screener restricted prices to.
+ */
+pub async fn preflight<T, Ex>(
+    exchange_short_code: Ex,
+    stages: &[Stage],
+    universe: Option<&[Box<str>]>,
+    bond_isins: &[String],
+    eodhd: &Eodhd<T>,
+    db: &Arc<dyn Storage>,
 ) -> Result<()>
 where
-    T: Display + Send + Sync + 'static,
-    D: Display + Send + Sync + 'static,
+    T: Display,
+    Ex: Display,
 {
-    let intraday_prices = eodhd
-        .get_high_resolution_historical_data(symbol.code.as_ref(), "US", None, None)
-        .await?;
+    let fn_text = "PREFLIGHT".bold().cyan();
+    let exchange_short_code = exchange_short_code.to_string();
+    let today = Utc::now().date_naive();
 
-    if intraday_prices.is_empty() {
-        println!(
-            "[{}] {}.US (isin: {}) {}",
-            &dump_prices_txt,
-            &symbol.code,
-            symbol.isin.as_ref().map(AsRef::as_ref).unwrap_or("missing"),
-            "EMPTY".underline()
-        );
-        return Ok(());
-    }
+    let user = eodhd.get_user().await?;
+    let unknown = || "unknown".into();
+    println!(
+        "[{}] Plan: {}, user: {}, expires: {}",
+        &fn_text,
+        user.subscription_type.clone().unwrap_or_else(unknown),
+        user.name.clone().unwrap_or_else(unknown),
+        user.subscription_expires.clone().unwrap_or_else(unknown),
+    );
+    let remaining = user.remaining_calls(today) as usize;
+    println!(
+        "[{}] Daily limit: {} (+{} extra), used today: {}, remaining: {}",
+        &fn_text, user.daily_rate_limit, user.extra_limit, user.api_requests, remaining
+    );
 
-    db.push_intraday(symbol.code.as_ref(), "US", &intraday_prices)
-        .await?;
+    let symbols = eodhd.get_exchange_symbols(&exchange_short_code).await?;
+    let count = |types: &[&str]| {
+        symbols
+            .iter()
+            .filter(|x| types.contains(&x.symbol_type.as_ref()))
+            .count()
+    };
+    let has_finished_prices: bool = load_serializable(prices_state_file(&exchange_short_code))
+        .await
+        .unwrap_or_default();
+
+    let mut estimate = 1;
+    for stage in stages {
+        let cost = match stage {
+            Stage::Intraday if has_finished_prices => 0,
+            Stage::Intraday => {
+                let scope = IntradayScope::load(&exchange_short_code, db).await?;
+                let resumed = prices_config(&exchange_short_code)
+                    .await
+                    .get_filter()
+                    .await
+                    .into_iter()
+                    .collect::<HashSet<_>>();
+                symbols
+                    .iter()
+                    .filter(|x| universe.is_none_or(|universe| universe.contains(&x.code)))
+                    .filter(|x| scope.is_outdated(&x.code))
+                    .filter(|x| !resumed.contains(format!("{}.{}", x.code, x.exchange).as_str()))
+                    .map(|x| eodhd.intraday_requests(scope.start_of(&x.code)))
+                    .sum::<usize>()
+                    * INTRADAY_CALL_COST
+            }
+            Stage::Calendar => 3,
+            Stage::Insider => {
+                let latest = match db.clone().mysql() {
+                    Some(mysql) => {
+                        mysql
+                            .get_latest_insider_transaction_date(&exchange_short_code)
+                            .await?
+                    }
+                    None => None,
+                };
+                let from = latest.unwrap_or(today - INSIDER_BACKFILL);
+                (today - from).num_days().max(0) as usize + 1
+            }
+            Stage::Options => count(&["Common Stock", "ETF"]) * OPTIONS_CALL_COST,
+            Stage::Bonds => {
+                let government_bonds = eodhd.get_exchange_symbols(GOVERNMENT_BOND_EXCHANGE).await?;
+                // Like `dump_bonds`, an ISIN that is both asked for and stored is fetched once
+                let mut isins = bond_isins
+                    .iter()
+                    .map(|x| x.to_uppercase().into_boxed_str())
+                    .collect::<HashSet<_>>();
+                if let Some(mysql) = db.clone().mysql() {
+                    isins.extend(mysql.get_bond_isins().await?);
+                }
+                1 + government_bonds.len() + isins.len()
+            }
+            Stage::MarketCap => count(&["Common Stock"]) * MARKET_CAP_CALL_COST,
+            Stage::Sentiment => count(&["Common Stock"]) * SENTIMENT_CALL_COST,
+            Stage::Aggregate => 0,
+        };
+        println!("[{}] {} costs at most {} calls", &fn_text, stage, cost);
+        estimate += cost;
+    }
 
+    if estimate > remaining {
+        bail!(
+            "The selected stages may need {estimate} API calls but only {remaining} are left today"
+        );
+    }
     println!(
-        "[{}] {}.US (isin: {}) {}st",
-        &dump_prices_txt,
-        &symbol.code,
-        symbol.isin.as_ref().map(AsRef::as_ref).unwrap_or("missing"),
-        intraday_prices.len()
+        "[{}] {} of {} remaining calls needed at most",
+        &fn_text, estimate, remaining
     );
-
     Ok(())
 }
 
-pub async fn selective_sync<T, S, Ex>(
-    exchange_short_code: Ex,
-    short_codes: Vec<S>,
-    eodhd: &Eodhd<T>,
-    db: &Db,
-) where
+/// Pulls upcoming earnings, IPOs and splits. A week back is included so that
+/// reported actuals replace the estimates.
+async fn dump_calendar<T>(exchange_short_code: &str, eodhd: &Eodhd<T>, db: &Db) -> Result<()>
+where
     T: Display,
-    S: Display,
-    Ex: Display,
 {
-    let config = SyncedConfig::<Box<str>>::load().await;
-    let mut downloaded = config.downloaded.clone().lock_owned().await;
+    let fn_text = "DUMP CALENDAR".bold().yellow();
+    let today = Utc::now().date_naive();
+    let (from, to) = (today - TimeDelta::days(7), today + CALENDAR_DAYS_AHEAD);
+    let on_exchange = |code: &str| split_code(code).1 == exchange_short_code;
 
-    let fn_text = format!("[{}]", "SELECTIVE SYNC".bold().yellow());
-    let exchange_short_code = exchange_short_code.to_string();
-    if let Err(_) = sync_metadata(&exchange_short_code, eodhd, db).await {
-        return;
-    }
-    eprintln!("{} Syncing {} instruments", &fn_text, short_codes.len());
+    let earnings = eodhd
+        .get_earnings_calendar(from, to)
+        .await?
+        .into_iter()
+        .filter(|x| on_exchange(&x.code))
+        .collect::<Vec<_>>();
+    db.push_earnings_calendar(&earnings).await?;
+    println!("[{}] {}st earnings", &fn_text, earnings.len());
 
-    for short_code in short_codes {
-        let short_code = short_code.to_string().to_uppercase();
-        let mut download_txt = fn_text.clone();
-        download_txt.push(' ');
-        download_txt.push_str(&short_code);
+    let ipos = eodhd
+        .get_ipo_calendar(from, to)
+        .await?
+        .into_iter()
+        .filter(|x| on_exchange(&x.code))
+        .collect::<Vec<_>>();
+    db.push_ipo_calendar(&ipos).await?;
+    println!("[{}] {}st IPOs", &fn_text, ipos.len());
 
-        let data = match eodhd
-            .get_high_resolution_historical_data(&short_code, &exchange_short_code, None, None)
-            .await
-        {
-            Ok(k) => k,
-            Err(e) => {
-                download_txt.push_str(format!(", Failed with error: {:?} ", e).as_str());
-                eprintln!("{}", download_txt);
-                continue;
-            }
-        };
+    let splits = eodhd
+        .get_splits_calendar(from, to)
+        .await?
+        .into_iter()
+        .filter(|x| on_exchange(&x.code))
+        .collect::<Vec<_>>();
+    db.push_splits_calendar(&splits).await?;
+    println!("[{}] {}st splits", &fn_text, splits.len());
 
-        download_txt.push_str(format!(", with {}st points", data.len()).as_str());
-        if let Err(e) = db
-            .push_intraday(&short_code, &exchange_short_code, &data)
+    Ok(())
+}
+
+/**
+ * Government bond yields from the `GBOND` exchange, continued from the latest stored day,
+ * and fundamentals of `isins` and every bond we already have fundamentals for. EODHD has no
+ * listing of corporate bonds, so new ones have to be asked for by ISIN.
+ */
+async fn dump_bonds<T>(isins: &[String], eodhd: &Eodhd<T>, db: &Db) -> Result<()>
+where
+    T: Display,
+{
+    let fn_text = "DUMP BONDS".bold().yellow();
+    let error_txt = "ERROR".red();
+
+    let government_bonds = eodhd.get_exchange_symbols(GOVERNMENT_BOND_EXCHANGE).await?;
+    println!(
+        "[{}] {}st government bonds",
+        &fn_text,
+        government_bonds.len()
+    );
+    for bond in government_bonds {
+        let code = bond.code.as_ref();
+        let from = db.get_latest_government_bond_yield_date(code).await?;
+        let result = match eodhd
+            .get_eod(format!("{code}.{GOVERNMENT_BOND_EXCHANGE}"), from)
             .await
         {
-            download_txt.push_str(format!(", failed to push to DB with error: {:?}", e).as_str());
-        } else {
-            let short_code = short_code.into_boxed_str();
-            if !downloaded.contains(&short_code) {
-                downloaded.push(short_code);
-            }
+            Ok(yields) => db
+                .push_government_bond_yields(code, &yields)
+                .await
+                .map(|_| yields.len()),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(n) => println!("[{}] {} {}st days", &fn_text, code, n),
+            Err(e) => eprintln!(
+                "[{}] ({}) {} failed with error: {:?}",
+                &fn_text, &error_txt, code, e
+            ),
         }
+    }
 
-        eprintln!("{}", download_txt);
-        config.save(None).await.unwrap();
+    let mut isins = isins
+        .iter()
+        .map(|x| x.to_uppercase().into_boxed_str())
+        .chain(db.get_bond_isins().await?)
+        .collect::<Vec<_>>();
+    isins.sort();
+    isins.dedup();
+    if isins.is_empty() {
+        println!(
+            "[{}] No bonds to fetch fundamentals for, pass their ISINs with --bond-isins",
+            &fn_text
+        );
     }
+    println!("[{}] {}st bond fundamentals", &fn_text, isins.len());
+    for isin in isins {
+        match eodhd.get_bond_fundamentals(&isin).await {
+            Ok(Some(bond)) => db.push_bond_fundamental(&bond).await?,
+            Ok(None) => println!("[{}] {} {}", &fn_text, &isin, "EMPTY".underline()),
+            Err(e) => eprintln!(
+                "[{}] ({}) {} failed with error: {:?}",
+                &fn_text, &error_txt, &isin, e
+            ),
+        }
+    }
+
+    Ok(())
 }
 
-async fn sync_metadata<T>(exchange_short_code: &str, eodhd: &Eodhd<T>, db: &Db) -> Result<()>
+/// Fetches filings one day at a time from the latest one stored, paging through each day.
+async fn dump_insider_transactions<T>(
+    exchange_short_code: &str,
+    eodhd: &Eodhd<T>,
+    db: &Db,
+) -> Result<()>
 where
     T: Display,
 {
-    let fn_text = "SYNC METADATA".bold().green();
+    let fn_text = "DUMP INSIDER".bold().yellow();
+    let today = Utc::now().date_naive();
+    let mut day = db
+        .get_latest_insider_transaction_date(exchange_short_code)
+        .await?
+        .unwrap_or(today - INSIDER_BACKFILL);
+
+    println!("[{}] Fetching filings from {}", &fn_text, day);
+    while day <= today {
+        let transactions = eodhd
+            .get_insider_transactions(None, day, day)
+            .await?
+            .into_iter()
+            .filter(|x| x.exchange.as_ref() == exchange_short_code)
+            .collect::<Vec<_>>();
+
+        let inserted = db.push_insider_transactions(&transactions).await?;
+        println!(
+            "[{}] {} {}st new of {}st",
+            &fn_text,
+            day,
+            inserted,
+            transactions.len()
+        );
+        day += TimeDelta::days(1);
+    }
+
+    Ok(())
+}
+
+/// What `screener_universe` saves, so that a rerun dumps the same symbols
+#[derive(Serialize, Deserialize)]
+struct ScreenedUniverse {
+    filters: ScreenerFilters,
+    screened_at: NaiveDate,
+    codes: Vec<Box<str>>,
+}
+
+/**
+ * The codes on `exchange_short_code` that match `filters`. The result is saved to
+ * `universe-{exchange}.json` and reused as long as the filters are the same, unless `refresh` is set.
+ */
+pub async fn screener_universe<T, Ex>(
+    exchange_short_code: Ex,
+    mut filters: ScreenerFilters,
+    refresh: bool,
+    eodhd: &Eodhd<T>,
+) -> Result<Vec<Box<str>>>
+where
+    T: Display,
+    Ex: Display,
+{
+    let screener_txt = "SCREENER".bold().cyan();
+    let exchange_short_code = exchange_short_code.to_string();
+    filters.exchange = Some(exchange_short_code.clone());
+    let universe_file = format!("universe-{exchange_short_code}.json");
+
+    if !refresh {
+        if let Ok(saved) = load_serializable::<ScreenedUniverse>(&universe_file).await {
+            if saved.filters == filters {
+                println!(
+                    "[{}] Reusing the {} symbols screened at {} from '{universe_file}'",
+                    &screener_txt,
+                    saved.codes.len(),
+                    saved.screened_at
+                );
+                return Ok(saved.codes);
+            }
+            println!(
+                "[{}] The filters changed since '{universe_file}' was saved. Will screen again",
+                &screener_txt
+            );
+        }
+    }
+
+    let codes = eodhd
+        .screen(&filters)
+        .await?
+        .into_iter()
+        .filter(|result| result.exchange.eq_ignore_ascii_case(&exchange_short_code))
+        .map(|result| result.code)
+        .collect::<Vec<_>>();
+    if codes.is_empty() {
+        bail!("The screener found no symbols on {exchange_short_code} matching {filters:?}");
+    }
+    println!(
+        "[{}] Screened {} symbols. Saving them to '{universe_file}'",
+        &screener_txt,
+        codes.len()
+    );
+
+    let universe = ScreenedUniverse {
+        filters,
+        screened_at: Utc::now().date_naive(),
+        codes: codes.clone(),
+    };
+    save_serializable_generic(&universe_file, universe).await?;
+    Ok(codes)
+}
+
+async fn dump_prices<T, Ex>(
+    exchange_short_code: Ex,
+    eodhd: Arc<Eodhd<T>>,
+    db: Arc<dyn Storage>,
+    threads: usize,
+    universe: Option<Vec<Box<str>>>,
+) -> Result<ExitedPrematurly>
+where
+    T: Display + Send + Sync + 'static + Serialize,
+    Ex: Display,
+{
+    let dump_prices_txt = Arc::new("DUMP PRICES".bold().purple());
+    let exchange_short_code: Arc<str> = exchange_short_code.to_string().into();
+    let config = Arc::new(prices_config(&exchange_short_code).await);
+    let mut symbols = eodhd.get_exchange_symbols(&exchange_short_code).await?;
+    if let Some(universe) = universe {
+        let universe = universe.into_iter().collect::<HashSet<_>>();
+        symbols.retain(|symbol| universe.contains(&symbol.code));
+        println!(
+            "[{}] Restricted to {} symbols from the screened universe",
+            &dump_prices_txt,
+            symbols.len()
+        );
+    }
+    let scope = Arc::new(IntradayScope::load(&exchange_short_code, &db).await?);
+    symbols.retain(|symbol| scope.is_outdated(&symbol.code));
+    println!(
+        "[{}] {} symbols are outdated",
+        &dump_prices_txt,
+        symbols.len()
+    );
+
+    let txt = dump_prices_txt.clone();
+    dump_symbols(
+        dump_prices_txt,
+        config,
+        symbols,
+        threads,
+        move |permit, symbol| {
+            process_symbol(
+                permit,
+                txt.clone(),
+                eodhd.clone(),
+                symbol,
+                exchange_short_code.clone(),
+                db.clone(),
+                scope.clone(),
+            )
+        },
+    )
+    .await
+}
+
+/**
+ * Snapshots the option chains of every stock and ETF on the exchange. Progress is kept per day,
+ * so an interrupted run resumes while tomorrow's run starts over.
+ */
+async fn dump_options<T, Ex>(
+    exchange_short_code: Ex,
+    eodhd: Arc<Eodhd<T>>,
+    db: Arc<Db>,
+    threads: usize,
+) -> Result<ExitedPrematurly>
+where
+    T: Display + Send + Sync + 'static,
+    Ex: Display,
+{
+    let dump_options_txt = Arc::new("DUMP OPTIONS".bold().purple());
+    let exchange_short_code: Arc<str> = exchange_short_code.to_string().into();
+    let today = Utc::now().date_naive();
+    let config = Arc::new(
+        SyncedConfig::<Arc<str>>::load_daily("options", &exchange_short_code, today).await,
+    );
+    let symbols = eodhd
+        .get_exchange_symbols(&exchange_short_code)
+        .await?
+        .into_iter()
+        .filter(|x| matches!(x.symbol_type.as_ref(), "Common Stock" | "ETF"))
+        .collect();
+
+    let txt = dump_options_txt.clone();
+    dump_symbols(
+        dump_options_txt,
+        config,
+        symbols,
+        threads,
+        move |permit, symbol| {
+            process_options_symbol(
+                permit,
+                txt.clone(),
+                eodhd.clone(),
+                symbol,
+                exchange_short_code.clone(),
+                db.clone(),
+                today,
+            )
+        },
+    )
+    .await
+}
+
+async fn process_options_symbol<T, D>(
+    _permit: OwnedSemaphorePermit,
+    dump_options_txt: D,
+    eodhd: Arc<Eodhd<T>>,
+    symbol: ExchangeSymbol,
+    exchange_short_code: Arc<str>,
+    db: Arc<Db>,
+    today: NaiveDate,
+) -> Result<()>
+where
+    T: Display + Send + Sync + 'static,
+    D: Display + Send + Sync + 'static,
+{
+    let ticker = format!("{}.{}", &symbol.code, &exchange_short_code);
+    let contracts = eodhd
+        .get_options(&ticker, today, today + OPTIONS_MAX_EXPIRY)
+        .await?;
+
+    if !contracts.is_empty() {
+        db.push_options(
+            symbol.code.as_ref(),
+            &exchange_short_code,
+            today,
+            &contracts,
+        )
+        .await?;
+    }
+    println!(
+        "[{}] {} {}st contracts",
+        &dump_options_txt,
+        &ticker,
+        contracts.len()
+    );
+
+    Ok(())
+}
+
+/**
+ * Continues the weekly market cap history of every stock from the latest stored date.
+ * Symbols updated within the last week are skipped since there can't be a new point yet.
+ */
+async fn dump_market_caps<T, Ex>(
+    exchange_short_code: Ex,
+    eodhd: Arc<Eodhd<T>>,
+    db: Arc<Db>,
+    threads: usize,
+) -> Result<ExitedPrematurly>
+where
+    T: Display + Send + Sync + 'static,
+    Ex: Display,
+{
+    let dump_market_caps_txt = Arc::new("DUMP MARKET CAPS".bold().purple());
+    let exchange_short_code: Arc<str> = exchange_short_code.to_string().into();
+    let today = Utc::now().date_naive();
+    let config = Arc::new(
+        SyncedConfig::<Arc<str>>::load_daily("market-cap", &exchange_short_code, today).await,
+    );
+    let symbols = eodhd
+        .get_exchange_symbols(&exchange_short_code)
+        .await?
+        .into_iter()
+        .filter(|x| x.symbol_type.as_ref() == "Common Stock")
+        .collect();
+
+    let txt = dump_market_caps_txt.clone();
+    dump_symbols(
+        dump_market_caps_txt,
+        config,
+        symbols,
+        threads,
+        move |permit, symbol| {
+            process_market_cap_symbol(
+                permit,
+                txt.clone(),
+                eodhd.clone(),
+                symbol,
+                exchange_short_code.clone(),
+                db.clone(),
+                today,
+            )
+        },
+    )
+    .await
+}
+
+async fn process_market_cap_symbol<T, D>(
+    _permit: OwnedSemaphorePermit,
+    dump_market_caps_txt: D,
+    eodhd: Arc<Eodhd<T>>,
+    symbol: ExchangeSymbol,
+    exchange_short_code: Arc<str>,
+    db: Arc<Db>,
+    today: NaiveDate,
+) -> Result<()>
+where
+    T: Display + Send + Sync + 'static,
+    D: Display + Send + Sync + 'static,
+{
+    let code = symbol.code.as_ref();
+    let latest = db
+        .get_latest_market_cap_date(code, &exchange_short_code)
+        .await?;
+    if latest.is_some_and(|latest| today - latest < TimeDelta::days(7)) {
+        return Ok(());
+    }
+
+    let from = latest.map(|latest| latest + TimeDelta::days(1));
+    let market_caps = eodhd
+        .get_historical_market_cap(format!("{code}.{exchange_short_code}"), from)
+        .await?;
+    db.push_market_caps(code, &exchange_short_code, &market_caps)
+        .await?;
+    println!(
+        "[{}] {}.{} {}st new points",
+        &dump_market_caps_txt,
+        code,
+        &exchange_short_code,
+        market_caps.len()
+    );
+
+    Ok(())
+}
+
+/**
+ * Daily sentiment of every common stock on the exchange, picking up after the latest stored day.
+ * Progress is kept per day like `dump_market_caps`.
+ */
+async fn dump_sentiments<T, Ex>(
+    exchange_short_code: Ex,
+    eodhd: Arc<Eodhd<T>>,
+    db: Arc<Db>,
+    threads: usize,
+) -> Result<ExitedPrematurly>
+where
+    T: Display + Send + Sync + 'static,
+    Ex: Display,
+{
+    let dump_sentiments_txt = Arc::new("DUMP SENTIMENT".bold().purple());
+    let exchange_short_code: Arc<str> = exchange_short_code.to_string().into();
+    let today = Utc::now().date_naive();
+    let config = Arc::new(
+        SyncedConfig::<Arc<str>>::load_daily("sentiment", &exchange_short_code, today).await,
+    );
+    let symbols = eodhd
+        .get_exchange_symbols(&exchange_short_code)
+        .await?
+        .into_iter()
+        .filter(|x| x.symbol_type.as_ref() == "Common Stock")
+        .collect();
+
+    let txt = dump_sentiments_txt.clone();
+    dump_symbols(
+        dump_sentiments_txt,
+        config,
+        symbols,
+        threads,
+        move |permit, symbol| {
+            process_sentiment_symbol(
+                permit,
+                txt.clone(),
+                eodhd.clone(),
+                symbol,
+                exchange_short_code.clone(),
+                db.clone(),
+                today,
+            )
+        },
+    )
+    .await
+}
+
+async fn process_sentiment_symbol<T, D>(
+    _permit: OwnedSemaphorePermit,
+    dump_sentiments_txt: D,
+    eodhd: Arc<Eodhd<T>>,
+    symbol: ExchangeSymbol,
+    exchange_short_code: Arc<str>,
+    db: Arc<Db>,
+    today: NaiveDate,
+) -> Result<()>
+where
+    T: Display + Send + Sync + 'static,
+    D: Display + Send + Sync + 'static,
+{
+    let code = symbol.code.as_ref();
+    let latest = db
+        .get_latest_sentiment_date(code, &exchange_short_code)
+        .await?;
+    if latest.is_some_and(|latest| latest >= today) {
+        return Ok(());
+    }
+
+    let from = latest.map(|latest| latest + TimeDelta::days(1));
+    let sentiments = eodhd
+        .get_sentiment(format!("{code}.{exchange_short_code}"), from)
+        .await?;
+    db.push_sentiments(code, &exchange_short_code, &sentiments)
+        .await?;
+    println!(
+        "[{}] {}.{} {}st new days",
+        &dump_sentiments_txt,
+        code,
+        &exchange_short_code,
+        sentiments.len()
+    );
+
+    Ok(())
+}
+
+/**
+ * Runs `process` for every symbol that isn't already downloaded or failed according to `config`,
+ * at most `threads` at a time. Progress is saved on Ctrl+C and when too many fail in a row,
+ * so that a rerun picks up where this one left off.
+ */
+async fn dump_symbols<F, Fut>(
+    dump_txt: Arc<ColoredString>,
+    config: Arc<SyncedConfig<Arc<str>>>,
+    symbols: Vec<ExchangeSymbol>,
+    threads: usize,
+    process: F,
+) -> Result<ExitedPrematurly>
+where
+    F: Fn(OwnedSemaphorePermit, ExchangeSymbol) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let error_txt = Arc::new("ERROR".red());
+    let max_errors_in_row: usize = threads * 2;
+
+    let filter_content = config.get_filter().await;
+    let (ctrl_c_handler, cancellation_token) = {
+        let local_config = config.clone();
+
+        let a = async move {
+            local_config
+                .save(None)
+                .await
+                .expect("Failed to save config");
+        };
+        get_ctrl_c_handler(a)
+    };
+
+    if filter_content.is_empty() {
+        println!("[{}] Filter files was empty", &dump_txt);
+    }
+    let filter: HashSet<&str> = HashSet::from_iter(filter_content.iter().map(|x| x.as_ref()));
+    let symbols = symbols.into_iter().filter(|symbol| {
+        let s = format!("{}.{}", symbol.code, symbol.exchange);
+        !filter.contains(s.as_str())
+    });
+
+    let semaphore = Arc::new(Semaphore::new(threads));
+    let errors_in_row = Arc::new(Mutex::new(0_usize));
+    let mut handles = Vec::new();
+
+    for symbol in symbols {
+        {
+            let errors_lock = errors_in_row.lock().await;
+            if *errors_lock > max_errors_in_row {
+                eprintln!(
+                    "[{}] We have failed {} times in a row. Exiting now...",
+                    &dump_txt, max_errors_in_row
+                );
+                config.save(None).await.expect("Failed to save config");
+                return Ok(ExitedPrematurly::Yes);
+            }
+            if cancellation_token.load(Ordering::SeqCst) {
+                eprintln!("[{}] Ctrl+C is pressed. Exiting...", &dump_txt);
+                return Ok(ExitedPrematurly::Yes);
+            }
+        }
+
+        let code_exchange = format!("{}.{}", symbol.code.as_ref(), symbol.exchange.as_ref());
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        let errors_in_row = errors_in_row.clone();
+        let config = config.clone();
+        let (dump_txt, error_txt) = (dump_txt.clone(), error_txt.clone());
+        let task = process(permit, symbol);
+        let handle = tokio::spawn(async move {
+            if let Err(e) = task.await {
+                let errors = {
+                    let mut errors_lock = errors_in_row.lock().await;
+                    (*errors_lock) += 1;
+                    *errors_lock
+                };
+                eprintln!(
+                    "[{}] ({}) ({}/{}) Failed to download/push {code_exchange} with error: {:?}",
+                    &dump_txt, &error_txt, errors, max_errors_in_row, &e
+                );
+                config.append_failure(code_exchange.into()).await;
+            } else {
+                config.append_download(code_exchange.into()).await;
+            }
+        });
+        handles.push(handle);
+    }
+
+    config
+        .save(Some(*errors_in_row.lock().await))
+        .await
+        .expect("Failed to save config/state");
+
+    let dump_txt = (*dump_txt).clone().blue();
+    println!("[{}] Waiting for all tasks to finish...", &dump_txt);
+    for handle in handles.into_iter() {
+        if let Err(e) = handle.await {
+            eprintln!(
+                "[{}] ({}) Encountered while awaiting all handles {:?}",
+                &dump_txt, &error_txt, &e
+            );
+        }
+    }
+    println!("[{}] Aborting ctrl-c handler...", &dump_txt);
+    ctrl_c_handler.abort();
+
+    Ok(ExitedPrematurly::No)
+}
+
+fn get_ctrl_c_handler<F>(save: F) -> (tokio::task::JoinHandle<()>, Arc<AtomicBool>)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let break_switch = Arc::new(AtomicBool::new(false));
+    let break_switch_c = break_switch.clone();
+    let handle = tokio::spawn(async move {
+        signal::ctrl_c().await.expect("Failed to listen for ctrl_c");
+        let ctrl_c_txt = "CTRL C HANDLER".blue().bold();
+        println!("[{}] Ctrl+C received! Saving state", &ctrl_c_txt,);
+        save.await;
+        break_switch_c.store(true, Ordering::SeqCst);
+    });
+    (handle, break_switch)
+}
+#[derive(Default)]
+enum ExitedPrematurly {
+    Yes,
+    #[default]
+    No,
+}
+
+async fn process_symbol<T, D>(
+    _permit: OwnedSemaphorePermit,
+    dump_prices_txt: D,
+    eodhd: Arc<Eodhd<T>>,
+    symbol: ExchangeSymbol,
+    exchange_short_code: Arc<str>,
+    db: Arc<dyn Storage>,
+    scope: Arc<IntradayScope>,
+) -> Result<()>
+where
+    T: Display + Send + Sync + 'static,
+    D: Display + Send + Sync + 'static,
+{
+    let suffix = AssetClass::of(&symbol).suffix(&exchange_short_code);
+    let intraday_prices = eodhd
+        .get_high_resolution_historical_data(
+            symbol.code.as_ref(),
+            suffix,
+            None,
+            scope.start_of(&symbol.code),
+            Some(&scope.calendar),
+        )
+        .await?;
+
+    if intraday_prices.is_empty() {
+        println!(
+            "[{}] {}.{} (isin: {}) {}",
+            &dump_prices_txt,
+            &symbol.code,
+            suffix,
+            symbol.isin.as_ref().map(AsRef::as_ref).unwrap_or("missing"),
+            "EMPTY".underline()
+        );
+        return Ok(());
+    }
+
+    db.push_intraday(symbol.code.as_ref(), &exchange_short_code, &intraday_prices)
+        .await?;
+
+    println!(
+        "[{}] {}.{} (isin: {}) {}st",
+        &dump_prices_txt,
+        &symbol.code,
+        suffix,
+        symbol.isin.as_ref().map(AsRef::as_ref).unwrap_or("missing"),
+        intraday_prices.len()
+    );
+
+    Ok(())
+}
+
+pub async fn selective_sync<T, S, Ex>(
+    exchange_short_code: Ex,
+    short_codes: Vec<S>,
+    eodhd: &Eodhd<T>,
+    db: &Arc<dyn Storage>,
+) where
+    T: Display,
+    S: Display,
+    Ex: Display,
+{
+    let config = SyncedConfig::<Box<str>>::load().await;
+    let mut downloaded = config.downloaded.clone().lock_owned().await;
+
+    let fn_text = format!("[{}]", "SELECTIVE SYNC".bold().yellow());
+    let exchange_short_code = exchange_short_code.to_string();
+    if sync_metadata(&exchange_short_code, eodhd, db.as_ref())
+        .await
+        .is_err()
+    {
+        return;
+    }
+    eprintln!("{} Syncing {} instruments", &fn_text, short_codes.len());
+    let calendar = match load_trading_calendar(&exchange_short_code, db).await {
+        Ok(calendar) => calendar,
+        Err(e) => {
+            eprintln!("{} Failed to load the trading calendar: {:?}", &fn_text, e);
+            return;
+        }
+    };
+    let max_from_date = match retention_horizon(&exchange_short_code, db).await {
+        Ok(max_from_date) => max_from_date,
+        Err(e) => {
+            eprintln!("{} Failed to load the retention: {:?}", &fn_text, e);
+            return;
+        }
+    };
+
+    for short_code in short_codes {
+        let short_code = short_code.to_string().to_uppercase();
+        let short_code = if IdKind::detect(&short_code) == Some(IdKind::Isin) {
+            let resolved = match require_mysql(db, "Resolving ISINs") {
+                Ok(mysql) => {
+                    resolve_identifier(
+                        IdKind::Isin,
+                        &short_code,
+                        Some(&exchange_short_code),
+                        eodhd,
+                        &mysql,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            match resolved {
+                Ok(Some((code, exchange))) if exchange.as_ref() == exchange_short_code => {
+                    eprintln!("{} {} resolved to {}", &fn_text, &short_code, &code);
+                    code.into_string()
+                }
+                Ok(Some((code, exchange))) => {
+                    eprintln!(
+                        "{} {} is listed as {}.{}, not on {}. Skipping",
+                        &fn_text, &short_code, &code, &exchange, &exchange_short_code
+                    );
+                    continue;
+                }
+                Ok(None) => {
+                    eprintln!("{} Couldn't resolve ISIN {}", &fn_text, &short_code);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "{} Failed to resolve ISIN {} with error: {:?}",
+                        &fn_text, &short_code, e
+                    );
+                    continue;
+                }
+            }
+        } else {
+            short_code
+        };
+        let mut download_txt = fn_text.clone();
+        download_txt.push(' ');
+        download_txt.push_str(&short_code);
+
+        let data = match eodhd
+            .get_high_resolution_historical_data(
+                &short_code,
+                &exchange_short_code,
+                None,
+                max_from_date,
+                Some(&calendar),
+            )
+            .await
+        {
+            Ok(k) => k,
+            Err(e) => {
+                download_txt.push_str(format!(", Failed with error: {:?} ", e).as_str());
+                eprintln!("{}", download_txt);
+                continue;
+            }
+        };
+
+        download_txt.push_str(format!(", with {}st points", data.len()).as_str());
+        if let Err(e) = db
+            .push_intraday(&short_code, &exchange_short_code, &data)
+            .await
+        {
+            download_txt.push_str(format!(", failed to push to DB with error: {:?}", e).as_str());
+        } else {
+            let short_code = short_code.into_boxed_str();
+            if !downloaded.contains(&short_code) {
+                downloaded.push(short_code);
+            }
+        }
+
+        eprintln!("{}", download_txt);
+        config.save(None).await.unwrap();
+    }
+}
+
+async fn sync_metadata<T>(
+    exchange_short_code: &str,
+    eodhd: &Eodhd<T>,
+    db: &dyn Storage,
+) -> Result<()>
+where
+    T: Display,
+{
+    let fn_text = "SYNC METADATA".bold().green();
     eprintln!("{} Downloading all instrument metadatas", &fn_text);
     // Make sure that we have the symbols in the DB
-    let all_instruments = match eodhd.get_exchange_symbols(&exchange_short_code).await {
+    let all_instruments = match eodhd.get_exchange_symbols(exchange_short_code).await {
         Ok(k) => k,
         Err(e) => {
             eprintln!(
                 "{} Failed to download instrument metadatas with error: {:?}",
                 &fn_text, &e
             );
-            return Err(e);
+            return Err(e);
+        }
+    };
+    eprintln!("{} Pushing metdata to DB", &fn_text);
+    let diff = match db
+        .push_exchange_symbols(exchange_short_code, all_instruments)
+        .await
+    {
+        Ok(k) => k,
+        Err(e) => {
+            eprintln!(
+                "{} Failed to push instrument metadatas with error: {:?}",
+                &fn_text, &e
+            );
+            return Err(e);
+        }
+    };
+
+    eprintln!("{} Downloading delisted instrument metadatas", &fn_text);
+    let delisted = match eodhd
+        .get_delisted_exchange_symbols(exchange_short_code)
+        .await
+    {
+        Ok(k) => k,
+        Err(e) => {
+            eprintln!(
+                "{} Failed to download delisted instrument metadatas with error: {:?}",
+                &fn_text, &e
+            );
+            return Err(e);
+        }
+    };
+    eprintln!("{} Pushing {}st delisted to DB", &fn_text, delisted.len());
+    if let Err(e) = db
+        .push_delisted_exchange_symbols(exchange_short_code, delisted)
+        .await
+    {
+        eprintln!(
+            "{} Failed to push delisted instrument metadatas with error: {:?}",
+            &fn_text, &e
+        );
+        return Err(e);
+    }
+    db.backfill_seen_dates(exchange_short_code).await?;
+
+    eprintln!(
+        "{} Done. {}st added, {}st removed, {}st fields changed",
+        fn_text, diff.added, diff.removed, diff.changed
+    );
+    Ok(())
+}
+
+pub async fn snapshot<T, S, Ex>(
+    exchange_short_code: Ex,
+    short_codes: Vec<S>,
+    eodhd: &Eodhd<T>,
+    db: &Db,
+) -> Result<()>
+where
+    T: Display,
+    S: Display,
+    Ex: Display,
+{
+    let fn_text = "SNAPSHOT".bold().cyan();
+    let exchange_short_code = exchange_short_code.to_string();
+
+    // Without any codes we snapshot everything we know of on the exchange
+    let short_codes: Vec<Box<str>> = if short_codes.is_empty() {
+        db.get_exchange_symbol_codes(&exchange_short_code).await?
+    } else {
+        short_codes
+            .into_iter()
+            .map(|code| code.to_string().to_uppercase().into_boxed_str())
+            .collect()
+    };
+    let tickers = short_codes
+        .iter()
+        .map(|code| format!("{}.{}", code, &exchange_short_code))
+        .collect::<Vec<_>>();
+    eprintln!("[{}] Fetching quotes for {} instruments", &fn_text, tickers.len());
+
+    let quotes = eodhd.get_real_time(&tickers).await?;
+    eprintln!("[{}] Got {} quotes. Pushing to DB", &fn_text, quotes.len());
+    let unknown = db.upsert_latest_quotes(&quotes).await?;
+    if !unknown.is_empty() {
+        eprintln!(
+            "[{}] Skipped {} quotes for symbols not in ExchangeSymbol: {}",
+            &fn_text,
+            unknown.len(),
+            unknown.join(", ")
+        );
+    }
+    eprintln!("[{}] Done", &fn_text);
+
+    Ok(())
+}
+
+/// Used when no indicators are asked for
+pub const DEFAULT_MACRO_INDICATORS: &[&str] = &[
+    "gdp_current_usd",
+    "gdp_growth_annual",
+    "inflation_consumer_prices_annual",
+    "unemployment_total_percent",
+    "real_interest_rate",
+];
+
+/// Keeps `MacroIndicator` and `EconomicEvent` up to date for the countries in `Exchange`.
+/// `countries` are ISO3 codes and limit the sync to those countries if not empty.
+pub async fn sync_macro<T, C, I>(
+    countries: Vec<C>,
+    indicators: Vec<I>,
+    eodhd: &Eodhd<T>,
+    db: &Db,
+) -> Result<()>
+where
+    T: Display,
+    C: Display,
+    I: Display,
+{
+    let fn_text = "SYNC MACRO".bold().green();
+    let error_txt = "ERROR".red();
+    let countries: HashSet<String> = countries
+        .into_iter()
+        .map(|x| x.to_string().to_uppercase())
+        .collect();
+    let indicators: Vec<String> = if indicators.is_empty() {
+        DEFAULT_MACRO_INDICATORS
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    } else {
+        indicators.into_iter().map(|x| x.to_string()).collect()
+    };
+
+    let all_countries = db.get_countries().await?;
+    let selected = all_countries
+        .iter()
+        .filter(|c| countries.is_empty() || countries.contains(c.iso3.as_ref()))
+        .collect::<Vec<_>>();
+    eprintln!("[{}] Syncing {} countries", &fn_text, selected.len());
+
+    let today = Utc::now().date_naive();
+    for country in selected {
+        for indicator in &indicators {
+            let result = match eodhd.get_macro_indicator(&country.iso3, indicator).await {
+                Ok(values) => db
+                    .push_macro_indicators(&country.iso3, indicator, &values)
+                    .await
+                    .map(|_| values.len()),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(n) => eprintln!("[{}] {} {} {}st", &fn_text, &country.iso3, indicator, n),
+                Err(e) => eprintln!(
+                    "[{}] ({}) {} {} failed with error: {:?}",
+                    &fn_text, &error_txt, &country.iso3, indicator, e
+                ),
+            }
+        }
+
+        // Go back a week to pick up actuals for events that were only estimated last time
+        let from = db
+            .get_latest_economic_event_date(&country.iso3)
+            .await?
+            .map(|latest| latest.date() - TimeDelta::days(7))
+            .unwrap_or(today - TimeDelta::days(365));
+        let to = today + TimeDelta::days(30);
+        let result = match eodhd.get_economic_events(&country.iso2, from, to).await {
+            Ok(events) => db
+                .push_economic_events(&country.iso3, &events)
+                .await
+                .map(|_| events.len()),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(n) => eprintln!("[{}] {} events {}st", &fn_text, &country.iso3, n),
+            Err(e) => eprintln!(
+                "[{}] ({}) {} events failed with error: {:?}",
+                &fn_text, &error_txt, &country.iso3, e
+            ),
+        }
+    }
+
+    eprintln!("[{}] Done", &fn_text);
+    Ok(())
+}
+
+/**
+ * Computes `indicators` for every symbol in `short_codes` (or the whole exchange if empty).
+ * The whole series is recomputed so that EMAs get their full history, but only dates
+ * after the latest stored one, less `TECHNICALS_REFRESH`, are written.
+ */
+pub async fn sync_technicals<S, Ex>(
+    exchange_short_code: Ex,
+    short_codes: Vec<S>,
+    indicators: Vec<Indicator>,
+    source: PriceSource,
+    db: &Db,
+) -> Result<()>
+where
+    S: Display,
+    Ex: Display,
+{
+    let fn_text = "TECHNICALS".bold().cyan();
+    let error_txt = "ERROR".red();
+    let exchange_short_code = exchange_short_code.to_string();
+    let indicators = if indicators.is_empty() {
+        DEFAULT_INDICATORS.to_vec()
+    } else {
+        indicators
+    };
+    let short_codes: Vec<Box<str>> = if short_codes.is_empty() {
+        db.get_exchange_symbol_codes(&exchange_short_code).await?
+    } else {
+        short_codes
+            .into_iter()
+            .map(|code| code.to_string().to_uppercase().into_boxed_str())
+            .collect()
+    };
+    eprintln!(
+        "[{}] Computing {} indicators for {} instruments",
+        &fn_text,
+        indicators.len(),
+        short_codes.len()
+    );
+
+    for code in short_codes {
+        let closes = match source {
+            PriceSource::Eod => db.get_daily_closes_eod(&code, &exchange_short_code).await?,
+            PriceSource::Intraday => {
+                db.get_daily_closes_intraday(&code, &exchange_short_code)
+                    .await?
+            }
+        };
+        if closes.is_empty() {
+            continue;
+        }
+        let latest = db
+            .get_latest_technical_dates(&code, &exchange_short_code)
+            .await?;
+        let (dates, closes): (Vec<_>, Vec<_>) = closes.into_iter().unzip();
+
+        let mut written = 0;
+        for indicator in &indicators {
+            for (name, series) in indicator.compute(&closes) {
+                let refresh_from = latest.get(&name).map(|x| *x - TECHNICALS_REFRESH);
+                let new_values = dates
+                    .iter()
+                    .zip(series)
+                    .filter(|(date, _)| refresh_from.is_none_or(|from| **date > from))
+                    .filter_map(|(date, value)| Some((*date, value?)))
+                    .collect::<Vec<_>>();
+                if let Err(e) = db
+                    .push_technicals(&code, &exchange_short_code, &name, &new_values)
+                    .await
+                {
+                    eprintln!(
+                        "[{}] ({}) {}.{} {} failed with error: {:?}",
+                        &fn_text, &error_txt, &code, &exchange_short_code, &name, e
+                    );
+                    continue;
+                }
+                written += new_values.len();
+            }
+        }
+        eprintln!(
+            "[{}] {}.{} {}st values written",
+            &fn_text, &code, &exchange_short_code, written
+        );
+    }
+
+    eprintln!("[{}] Done", &fn_text);
+    Ok(())
+}
+
+/**
+ * Resolves an ISIN, CUSIP or FIGI to a (code, exchange), preferring listings on `preferred_exchange`.
+ * Answers are cached in `CrossReferencedSymbols` so that each identifier only costs API calls once.
+ * ISINs the id-mapping endpoint doesn't know are looked up through search as well.
+ */
+pub async fn resolve_identifier<T>(
+    kind: IdKind,
+    identifier: &str,
+    preferred_exchange: Option<&str>,
+    eodhd: &Eodhd<T>,
+    db: &Db,
+) -> Result<Option<(Box<str>, Box<str>)>>
+where
+    T: Display,
+{
+    if let Some(cached) = db.get_cross_referenced_symbol(kind, identifier).await? {
+        return Ok(Some(cached));
+    }
+    let is_preferred = |exchange: &str| preferred_exchange.is_some_and(|p| p == exchange);
+
+    let mut mappings = eodhd.get_id_mapping(kind, identifier).await?;
+    let mut name = None;
+    if mappings.is_empty() && kind == IdKind::Isin {
+        let mut found = eodhd
+            .search(identifier, 10)
+            .await?
+            .into_iter()
+            .filter(|x| x.isin.as_deref() == Some(identifier))
+            .collect::<Vec<_>>();
+        found.sort_by_key(|x| !is_preferred(&x.exchange));
+        if let Some(first) = found.into_iter().next() {
+            name = first.name;
+            mappings.push(IdMapping {
+                symbol: format!("{}.{}", first.code, first.exchange).into_boxed_str(),
+                isin: Some(identifier.into()),
+                figi: None,
+                cusip: None,
+            });
+        }
+    }
+
+    mappings.sort_by_key(|x| !is_preferred(split_code(&x.symbol).1));
+    let Some(mapping) = mappings.into_iter().next() else {
+        return Ok(None);
+    };
+    let (code, exchange) = split_code(&mapping.symbol);
+    db.push_cross_referenced_symbol(code, exchange, name.as_deref(), &mapping)
+        .await?;
+
+    Ok(Some((code.into(), exchange.into())))
+}
+
+/**
+ * Maps a file of identifiers, one per line, to (code, exchange) and prints them as CSV.
+ * Unresolved identifiers get empty columns.
+ */
+pub async fn resolve<T>(
+    file: impl AsRef<Path>,
+    preferred_exchange: Option<&str>,
+    eodhd: &Eodhd<T>,
+    db: &Db,
+) -> Result<()>
+where
+    T: Display,
+{
+    let fn_text = "RESOLVE".bold().blue();
+    let error_txt = "ERROR".red();
+    let contents = tokio::fs::read_to_string(file).await?;
+    let identifiers = contents
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+        .collect::<Vec<_>>();
+    eprintln!("[{}] Resolving {} identifiers", &fn_text, identifiers.len());
+
+    println!("identifier,code,exchange");
+    let mut unresolved = 0;
+    for identifier in identifiers {
+        let identifier = identifier.to_uppercase();
+        let resolved = match IdKind::detect(&identifier) {
+            Some(kind) => {
+                resolve_identifier(kind, &identifier, preferred_exchange, eodhd, db).await
+            }
+            None => {
+                eprintln!(
+                    "[{}] ({}) {} is not an ISIN, CUSIP or FIGI",
+                    &fn_text, &error_txt, &identifier
+                );
+                Ok(None)
+            }
+        };
+
+        match resolved {
+            Ok(Some((code, exchange))) => println!("{identifier},{code},{exchange}"),
+            Ok(None) => {
+                unresolved += 1;
+                println!("{identifier},,");
+            }
+            Err(e) => {
+                unresolved += 1;
+                eprintln!(
+                    "[{}] ({}) {} failed with error: {:?}",
+                    &fn_text, &error_txt, &identifier, e
+                );
+                println!("{identifier},,");
+            }
+        }
+    }
+
+    eprintln!("[{}] Done, {}st unresolved", &fn_text, unresolved);
+    Ok(())
+}
+
+/**
+ * Rolls `StockPrice` up into `StockPriceAggregate`, in intervals aligned to the sessions of the
+ * exchange. Only bars from the start of the latest stored aggregate onwards are read, so rerunning
+ * after each sync is cheap. That latest aggregate is recomputed since it may have been built before
+ * its interval was over. Bars added or removed further back are recomputed by `reaggregate` as it
+ * happens. `rebuild` throws away what's stored and starts over.
+ */
+pub async fn aggregate_bars<S, Ex>(
+    exchange_short_code: Ex,
+    short_codes: Vec<S>,
+    intervals: Vec<BarInterval>,
+    rebuild: bool,
+    db: &Db,
+) -> Result<()>
+where
+    S: Display,
+    Ex: Display,
+{
+    let fn_text = "AGGREGATE".bold().cyan();
+    let error_txt = "ERROR".red();
+    let exchange_short_code = exchange_short_code.to_string();
+    let intervals = if intervals.is_empty() {
+        DEFAULT_INTERVALS.to_vec()
+    } else {
+        intervals
+    };
+    let short_codes: Vec<Box<str>> = if short_codes.is_empty() {
+        db.get_exchange_symbol_codes(&exchange_short_code).await?
+    } else {
+        short_codes
+            .into_iter()
+            .map(|code| code.to_string().to_uppercase().into_boxed_str())
+            .collect()
+    };
+    let calendar = db
+        .get_trading_calendar(&exchange_short_code)
+        .await?
+        .unwrap_or_else(|| TradingCalendar::fallback(&exchange_short_code));
+    eprintln!(
+        "[{}] Aggregating {} instruments into {} intervals",
+        &fn_text,
+        short_codes.len(),
+        intervals.len()
+    );
+
+    // StockPrice is never ahead of now, this just has to be later than the latest bar
+    let to = Utc::now() + TimeDelta::days(1);
+    for code in short_codes {
+        let mut written = 0;
+        for interval in intervals.iter().filter(|x| **x != BarInterval::Minutes5) {
+            if rebuild {
+                db.delete_aggregates(
+                    &code,
+                    &exchange_short_code,
+                    *interval,
+                    DateTime::<Utc>::default(),
+                    to,
+                )
+                .await?;
+            }
+            // The latest aggregate may have been built before all of its bars were stored
+            let from = db
+                .get_latest_aggregate(&code, &exchange_short_code, *interval)
+                .await?
+                .unwrap_or_default();
+            let bars = db.get_bars(
+                &code,
+                &exchange_short_code,
+                from - BUCKET_REACH,
+                to,
+                *interval,
+                &calendar,
+            );
+            let mut batches = buckets_within(bars, from, to).chunks(AGGREGATE_BATCH);
+            while let Some(batch) = batches.next().await {
+                let bars = batch.into_iter().collect::<sqlx::Result<Vec<_>>>()?;
+                if let Err(e) = db
+                    .push_aggregates(&code, &exchange_short_code, *interval, &bars)
+                    .await
+                {
+                    eprintln!(
+                        "[{}] ({}) {}.{} {} failed with error: {:?}",
+                        &fn_text, &error_txt, &code, &exchange_short_code, interval, e
+                    );
+                    break;
+                }
+                written += bars.len();
+            }
+        }
+        eprintln!(
+            "[{}] {}.{} {} bars",
+            &fn_text, &code, &exchange_short_code, written
+        );
+    }
+
+    eprintln!("[{}] Done", &fn_text);
+    Ok(())
+}
+
+/**
+ * Recomputes the aggregates of a symbol whose interval holds a bar from `from` to `to`, after bars
+ * there were added or removed. Intervals the symbol has no aggregates in are left to `aggregate_bars`.
+ */
+async fn reaggregate(
+    code: &str,
+    exchange_short_code: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    calendar: &TradingCalendar,
+    db: &Db,
+) -> Result<()> {
+    for interval in db
+        .get_aggregate_intervals(code, exchange_short_code)
+        .await?
+    {
+        let (first, last) = (
+            interval.bucket_start(from, calendar),
+            interval.bucket_start(to, calendar),
+        );
+        db.delete_aggregates(code, exchange_short_code, interval, first, last)
+            .await?;
+        let bars = db.get_bars(
+            code,
+            exchange_short_code,
+            first - BUCKET_REACH,
+            last + TimeDelta::days(1),
+            interval,
+            calendar,
+        );
+        let mut batches = buckets_within(bars, first, last).chunks(AGGREGATE_BATCH);
+        while let Some(batch) = batches.next().await {
+            let bars = batch.into_iter().collect::<sqlx::Result<Vec<_>>>()?;
+            db.push_aggregates(code, exchange_short_code, interval, &bars)
+                .await?;
         }
+    }
+    Ok(())
+}
+
+/**
+ * Checks the stored prices of `short_codes`, every symbol on the exchange if empty, and reports
+ * how often each rule was broken. With `quarantine` the offending rows are moved to `QuarantinedPrice`
+ * and the aggregates built from them are recomputed.
+ */
+pub async fn validate_prices<S, Ex>(
+    exchange_short_code: Ex,
+    short_codes: Vec<S>,
+    validator: &Validator,
+    quarantine: bool,
+    db: &Db,
+) -> Result<()>
+where
+    S: Display,
+    Ex: Display,
+{
+    let fn_text = "VALIDATE".bold().cyan();
+    let exchange_short_code = exchange_short_code.to_string();
+    let short_codes: Vec<Box<str>> = if short_codes.is_empty() {
+        db.get_exchange_symbol_codes(&exchange_short_code).await?
+    } else {
+        short_codes
+            .into_iter()
+            .map(|code| code.to_string().to_uppercase().into_boxed_str())
+            .collect()
     };
-    eprintln!("{} Pushing metdata to DB", &fn_text);
-    if let Err(e) = db
-        .push_exchange_symbols(&exchange_short_code, all_instruments)
+    eprintln!(
+        "[{}] Checking {} instruments against {} rules",
+        &fn_text,
+        short_codes.len(),
+        validator.rules.len()
+    );
+
+    let calendar = db
+        .get_trading_calendar(&exchange_short_code)
+        .await?
+        .unwrap_or_else(|| TradingCalendar::fallback(&exchange_short_code));
+
+    let mut totals: HashMap<Rule, usize> = HashMap::new();
+    for code in short_codes {
+        for table in [PriceTable::Intraday, PriceTable::Eod] {
+            let rows = db.get_stored_prices(&code, &exchange_short_code, table);
+            let violations = validator.check_stream(rows, VALIDATE_BATCH).await?;
+            if violations.is_empty() {
+                continue;
+            }
+
+            let mut counts: HashMap<Rule, usize> = HashMap::new();
+            for (_, rule) in &violations {
+                *counts.entry(*rule).or_default() += 1;
+                *totals.entry(*rule).or_default() += 1;
+            }
+            let counts = Rule::ALL
+                .iter()
+                .filter_map(|rule| Some(format!("{} {}", rule, counts.get(rule)?)))
+                .collect::<Vec<_>>();
+            println!(
+                "[{}] {}.{} {}: {}",
+                &fn_text,
+                &code,
+                &exchange_short_code,
+                table.as_str(),
+                counts.join(", ")
+            );
+
+            if quarantine {
+                db.quarantine_prices(&code, &exchange_short_code, table, &violations)
+                    .await?;
+                if table == PriceTable::Intraday {
+                    // Sorted by timestamp, like the rows they came from
+                    let (from, to) = (&violations[0].0, &violations[violations.len() - 1].0);
+                    reaggregate(
+                        &code,
+                        &exchange_short_code,
+                        from.timestamp.and_utc(),
+                        to.timestamp.and_utc(),
+                        &calendar,
+                        db,
+                    )
+                    .await?;
+                }
+            }
+        }
+    }
+
+    for rule in Rule::ALL {
+        println!(
+            "[{}] {}: {}",
+            &fn_text,
+            rule,
+            totals.get(rule).copied().unwrap_or_default()
+        );
+    }
+    if quarantine {
+        println!(
+            "[{}] Moved {} rows to QuarantinedPrice",
+            &fn_text,
+            totals.values().sum::<usize>()
+        );
+    }
+    Ok(())
+}
+
+/**
+ * Lists the sessions each symbol has no intraday bars for, between its first and last stored bar.
+ * With `fill` every gap is downloaded again, with exactly its bounds, and the aggregates over it
+ * are recomputed.
+ */
+pub async fn intraday_gaps<T, S, Ex>(
+    exchange_short_code: Ex,
+    short_codes: Vec<S>,
+    fill: bool,
+    eodhd: &Eodhd<T>,
+    db: &Db,
+) -> Result<()>
+where
+    T: Display,
+    S: Display,
+    Ex: Display,
+{
+    let fn_text = "GAPS".bold().cyan();
+    let error_txt = "ERROR".red();
+    let exchange_short_code = exchange_short_code.to_string();
+    let short_codes: Vec<Box<str>> = if short_codes.is_empty() {
+        db.get_exchange_symbol_codes(&exchange_short_code).await?
+    } else {
+        short_codes
+            .into_iter()
+            .map(|code| code.to_string().to_uppercase().into_boxed_str())
+            .collect()
+    };
+    let calendar = db
+        .get_trading_calendar(&exchange_short_code)
+        .await?
+        .unwrap_or_else(|| TradingCalendar::fallback(&exchange_short_code));
+    eprintln!(
+        "[{}] Looking for gaps in {} instruments",
+        &fn_text,
+        short_codes.len()
+    );
+
+    let (mut total_gaps, mut total_sessions, mut filled) = (0, 0, 0);
+    for code in short_codes {
+        let days = db
+            .get_bar_days(&code, &exchange_short_code, &calendar)
+            .await?;
+        let gaps = find_gaps(&days, |date| calendar.is_session(date));
+        if gaps.is_empty() {
+            continue;
+        }
+        for gap in &gaps {
+            println!(
+                "[{}] {}.{} {} to {}: {} sessions",
+                &fn_text, &code, &exchange_short_code, gap.from, gap.to, gap.sessions
+            );
+        }
+        total_gaps += gaps.len();
+        total_sessions += gaps.iter().map(|x| x.sessions).sum::<usize>();
+
+        if !fill {
+            continue;
+        }
+        for gap in gaps {
+            let (max_from_date, to_date) = gap.bounds(&calendar);
+            let prices = match eodhd
+                .get_high_resolution_historical_data(
+                    &code,
+                    &exchange_short_code,
+                    Some(to_date),
+                    Some(max_from_date),
+                    Some(&calendar),
+                )
+                .await
+            {
+                Ok(prices) => prices,
+                Err(e) => {
+                    eprintln!(
+                        "[{}] ({}) {}.{} {} to {} failed with error: {:?}",
+                        &fn_text, &error_txt, &code, &exchange_short_code, gap.from, gap.to, e
+                    );
+                    continue;
+                }
+            };
+            if prices.is_empty() {
+                continue;
+            }
+            filled += prices.len();
+            db.push_intraday(&code, &exchange_short_code, &prices)
+                .await?;
+            let (from, to) = prices
+                .iter()
+                .fold((prices[0].datetime, prices[0].datetime), |(from, to), x| {
+                    (from.min(x.datetime), to.max(x.datetime))
+                });
+            reaggregate(&code, &exchange_short_code, from, to, &calendar, db).await?;
+        }
+    }
+
+    println!(
+        "[{}] {} gaps, {} sessions missing",
+        &fn_text, total_gaps, total_sessions
+    );
+    if fill {
+        println!("[{}] Filled in {} bars", &fn_text, filled);
+    }
+    Ok(())
+}
+
+/**
+ * Stores the session hours and holidays of an exchange, from the start of intraday history until a
+ * year ahead. Nothing is stored when EODHD can't be asked, so the built in calendar is used until it can.
+ */
+pub async fn sync_exchange_details<T, Ex>(
+    exchange_short_code: Ex,
+    eodhd: &Eodhd<T>,
+    db: &Db,
+) -> Result<()>
+where
+    T: Display,
+    Ex: Display,
+{
+    let fn_text = "EXCHANGE DETAILS".bold().cyan();
+    let error_txt = "ERROR".red();
+    let exchange_short_code = exchange_short_code.to_string();
+    let today = Utc::now().date_naive();
+    let from = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+
+    let calendar = match eodhd
+        .get_exchange_details(&exchange_short_code, from, today + TimeDelta::days(365))
         .await
     {
-        eprintln!(
-            "{} Failed to push instrument metadatas with error: {:?}",
-            &fn_text, &e
+        Ok(details) => TradingCalendar::from_details(details, today),
+        Err(e) => {
+            eprintln!(
+                "[{}] ({}) {} failed with error: {:?}. Keeping the stored calendar, or the built in one",
+                &fn_text, &error_txt, &exchange_short_code, e
+            );
+            return Ok(());
+        }
+    };
+    db.push_trading_calendar(&calendar).await?;
+
+    println!(
+        "[{}] {} trades {} to {} ({}), {} UTC, with {} holidays",
+        &fn_text,
+        &exchange_short_code,
+        calendar.open,
+        calendar.close,
+        calendar.timezone.as_deref().unwrap_or("unknown time zone"),
+        calendar.open_utc,
+        calendar.holidays.len()
+    );
+    match calendar.next_open(Utc::now()) {
+        Some(next) if !calendar.is_open(Utc::now()) => {
+            println!("[{}] Closed, opens next at {} UTC", &fn_text, next)
+        }
+        Some(_) => println!("[{}] Open now", &fn_text),
+        None => println!("[{}] No session within a year", &fn_text),
+    }
+    Ok(())
+}
+
+/**
+ * Keeps `StockPrice` partitioned by month: partitions `months_ahead` months into the future are
+ * created, and months older than the retention of an exchange are exported to `archive_dir` as
+ * gzipped CSV before they're removed. A partition is dropped once every exchange in it is past its
+ * retention, otherwise only the expired exchanges are deleted from it. Exchanges without a retention,
+ * and no `default_retention`, are kept forever. The retention is stored, so intraday syncs stop at it.
+ * `init` partitions a table that isn't yet.
+ */
+pub async fn manage_partitions(
+    retentions: &[Retention],
+    default_retention: Option<u32>,
+    months_ahead: u32,
+    archive_dir: &Path,
+    init: bool,
+    db: &Db,
+) -> Result<()> {
+    let fn_text = "PARTITIONS".bold().cyan();
+    let this_month = first_of_month(Utc::now().date_naive());
+    let last_month = this_month + Months::new(months_ahead);
+
+    db.push_retentions(retentions, default_retention).await?;
+    let mut partitions = db.get_stock_price_partitions().await?;
+    if partitions.iter().all(|x| x.name.is_none()) {
+        if !init {
+            bail!("StockPrice isn't partitioned yet. Rerun with --init to partition it, which drops its foreign keys and rebuilds the table");
+        }
+        let first = db.get_first_stock_price_date().await?.unwrap_or(this_month);
+        let months = months_between(first, last_month);
+        println!(
+            "[{}] Partitioning StockPrice into {} months, this can take a while",
+            &fn_text,
+            months.len()
         );
-        return Err(e);
+        db.partition_stock_price(&months).await?;
+        partitions = db.get_stock_price_partitions().await?;
+    }
+    let months = partitions
+        .iter()
+        .filter_map(|x| {
+            let name = x.name.clone()?;
+            Some((partition_month(&name)?, name))
+        })
+        .collect::<Vec<_>>();
+
+    let next = months
+        .last()
+        .map(|(month, _)| next_month(*month))
+        .unwrap_or(this_month);
+    let new_months = months_between(next, last_month);
+    if !new_months.is_empty() {
+        db.add_stock_price_partitions(&new_months).await?;
+        println!(
+            "[{}] Added {} partitions up to {}",
+            &fn_text,
+            new_months.len(),
+            last_month.format("%Y-%m")
+        );
+    }
+
+    let retention_of = |exchange: &str| {
+        retentions
+            .iter()
+            .find(|x| *x.exchange == *exchange)
+            .map(|x| x.months)
+            .or(default_retention)
+    };
+    std::fs::create_dir_all(archive_dir)?;
+    for (month, partition) in months {
+        let exchanges = db.get_partition_exchanges(&partition).await?;
+        let expired = exchanges
+            .iter()
+            .filter(|x| is_expired(month, retention_of(x), this_month))
+            .collect::<Vec<_>>();
+        let drop = match exchanges.is_empty() {
+            true => is_expired(month, default_retention, this_month),
+            false => expired.len() == exchanges.len(),
+        };
+        if expired.is_empty() && !drop {
+            continue;
+        }
+
+        for exchange in &expired {
+            let file = archive_dir.join(format!(
+                "StockPrice-{}-{}.csv.gz",
+                exchange,
+                month.format("%Y-%m")
+            ));
+            let rows = export_partition(db, &partition, exchange, &file).await?;
+            println!(
+                "[{}] Archived {} rows of {} {} to {}",
+                &fn_text,
+                rows,
+                exchange,
+                month.format("%Y-%m"),
+                file.display()
+            );
+        }
+
+        if drop {
+            db.drop_stock_price_partition(&partition).await?;
+            println!("[{}] Dropped {}", &fn_text, &partition);
+        } else {
+            for exchange in expired {
+                let deleted = db
+                    .delete_partition_prices(&partition, exchange, RETENTION_DELETE_BATCH)
+                    .await?;
+                println!(
+                    "[{}] Deleted {} rows of {} from {}",
+                    &fn_text, deleted, exchange, &partition
+                );
+            }
+        }
     }
-    eprintln!("{} Done", fn_text);
+
+    println!("[{}] Done", &fn_text);
     Ok(())
 }
+
+/// Written next to `file` first, so an interrupted export never looks like a finished archive
+async fn export_partition(db: &Db, partition: &str, exchange: &str, file: &Path) -> Result<u64> {
+    let unfinished = file.with_extension("gz.part");
+    let mut out = GzEncoder::new(
+        BufWriter::new(std::fs::File::create(&unfinished)?),
+        Compression::default(),
+    );
+    let rows = db
+        .export_partition_prices(partition, exchange, &mut out)
+        .await?;
+    out.finish()?.flush()?;
+    std::fs::rename(&unfinished, file)?;
+    Ok(rows)
+}
@@ -1,8 +1,9 @@
 use std::{fmt::Display, time::Duration};
 
 use anyhow::{anyhow, Result};
-use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use chrono::{DateTime, NaiveDate, TimeDelta, TimeZone, Utc};
 use colored::{ColoredString, Colorize};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use reqwest::Client;
 use reqwest::IntoUrl;
 use serde::de::DeserializeOwned;
@@ -11,9 +12,28 @@ use tokio::time;
 
 use crate::models::ExchangeSymbol;
 use crate::models::Intraday;
+use crate::models::OptionContract;
+use crate::models::Quote;
+use crate::models::{BondFundamental, DailySentiment, Eod, MarketCap};
+use crate::models::{CalendarEarning, CalendarIpo, CalendarSplit, InsiderTransaction};
+use crate::models::{EconomicEvent, MacroIndicator};
+use crate::models::{ExchangeDetails, ExchangeHoliday};
+use crate::models::{IdKind, IdMapping, SearchResult};
+use crate::models::{ScreenerFilters, ScreenerResult, UserInfo};
+use crate::trading_calendar::TradingCalendar;
 
 const TIMEDELTA: TimeDelta = TimeDelta::days(120);
-const API_URL: &'static str = "https://eodhd.com/api";
+/// Sessions around the clock would otherwise reserve room for bars that mostly never come
+const MAX_PREALLOCATED_BARS: usize = 100_000;
+/// EODHD recommends at most 15-20 tickers per real-time request
+const REAL_TIME_CHUNK: usize = 15;
+/// Page sizes of the endpoints paged with `offset`
+const INSIDER_TRANSACTIONS_LIMIT: usize = 1000;
+const ECONOMIC_EVENTS_LIMIT: usize = 1000;
+/// The screener gives at most 100 rows per page and won't go past offset 999
+const SCREENER_PAGE: usize = 100;
+const SCREENER_MAX_OFFSET: usize = 999;
+const API_URL: &str = "https://eodhd.com/api";
 
 pub struct Eodhd<T>
 where
@@ -45,33 +65,59 @@ where
         }
     }
 
+    /**
+     * 5 minute bars from `max_from_date` to `to_date`, fetched in windows of 120 days, newest first.
+     * Nothing before the start of EODHD's intraday history is asked for. With a `calendar`, windows
+     * without a single session aren't asked for.
+     */
     pub async fn get_high_resolution_historical_data(
         &self,
         ticker: impl Display,
         exchange_short_code: impl Display,
         to_date: Option<DateTime<Utc>>,
         max_from_date: Option<DateTime<Utc>>,
+        calendar: Option<&TradingCalendar>,
     ) -> Result<Vec<Intraday>> {
         let mut lower_time_limit = None;
 
         let mut to_date = to_date.unwrap_or_else(|| chrono::Local::now().to_utc());
-        let max_from_date = max_from_date.unwrap_or_else(|| self.lower_intraday_bound_timestamp);
+        let max_from_date = self.intraday_from(max_from_date);
 
+        // Capacity only
         let v_size = {
-            let week_days = (to_date - max_from_date).num_days() * 5 / 7;
-            if week_days < 0 {
+            let days = (to_date - max_from_date).num_days();
+            if days < 0 {
                 return Err(anyhow!("to_date must be more than max_from_date"));
             }
-            week_days as usize
+            match calendar {
+                Some(calendar) => {
+                    let sessions = calendar
+                        .sessions(max_from_date.date_naive(), to_date.date_naive())
+                        .count();
+                    sessions * (calendar.session_length().num_minutes() as usize / 5)
+                }
+                None => days as usize,
+            }
+            .min(MAX_PREALLOCATED_BARS)
         };
 
         let mut intradays = Vec::with_capacity(v_size);
         while to_date > max_from_date {
-            let from_date = if max_from_date - to_date >= TIMEDELTA {
+            let from_date = if to_date - max_from_date <= TIMEDELTA {
                 max_from_date
             } else {
                 to_date - TIMEDELTA
             };
+            let has_sessions = calendar.is_none_or(|calendar| {
+                calendar
+                    .sessions(from_date.date_naive(), to_date.date_naive())
+                    .next()
+                    .is_some()
+            });
+            if !has_sessions {
+                to_date -= TIMEDELTA;
+                continue;
+            }
 
             let url = format!(
             "{API_URL}/intraday/{ticker}.{exchange_short_code}?api_token={}&interval=5m&fmt=json&from={}&to={}",
@@ -99,6 +145,58 @@ where
         Ok(intradays)
     }
 
+    /// `max_from_date`, but not before the start of EODHD's intraday history
+    fn intraday_from(&self, max_from_date: Option<DateTime<Utc>>) -> DateTime<Utc> {
+        max_from_date.map_or(self.lower_intraday_bound_timestamp, |x| {
+            x.max(self.lower_intraday_bound_timestamp)
+        })
+    }
+
+    /// How many requests `get_high_resolution_historical_data` makes at most up to now
+    pub fn intraday_requests(&self, max_from_date: Option<DateTime<Utc>>) -> usize {
+        let seconds = (Utc::now() - self.intraday_from(max_from_date)).num_seconds();
+        (seconds.max(0) as usize).div_ceil(TIMEDELTA.num_seconds() as usize)
+    }
+
+    /// Trading hours and the holidays from `from` to `to`
+    pub async fn get_exchange_details(
+        &self,
+        exchange_short_code: impl Display,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<ExchangeDetails> {
+        let url = format!(
+            "{API_URL}/exchange-details/{exchange_short_code}?api_token={}&fmt=json&from={}&to={}",
+            self.api_token,
+            from.format("%Y-%m-%d"),
+            to.format("%Y-%m-%d")
+        );
+
+        let value = self.get_url::<Value, _>(&url).await?;
+        let holidays = match value.get("ExchangeHolidays") {
+            Some(Value::Object(map)) => map.values().cloned().collect(),
+            Some(Value::Array(holidays)) => holidays.clone(),
+            _ => Vec::new(),
+        };
+        let mut details: ExchangeDetails = serde_json::from_value(value)?;
+        details.holidays = holidays
+            .into_iter()
+            .map(serde_json::from_value)
+            .filter_map(Result::ok)
+            .collect::<Vec<ExchangeHoliday>>();
+
+        Ok(details)
+    }
+
+    /// The plan and usage of the API token. Doesn't count against the daily limit
+    pub async fn get_user(&self) -> Result<UserInfo> {
+        let url = format!("{API_URL}/user?api_token={}&fmt=json", self.api_token);
+
+        Ok(serde_json::from_value(
+            self.get_url::<Value, _>(&url).await?,
+        )?)
+    }
+
     pub async fn get_exchange_symbols(
         &self,
         exchange_short_code: impl Display,
@@ -117,6 +215,471 @@ where
             .collect())
     }
 
+    /**
+     * Symbols that used to trade on the exchange but have since been delisted
+     */
+    pub async fn get_delisted_exchange_symbols(
+        &self,
+        exchange_short_code: impl Display,
+    ) -> Result<Vec<ExchangeSymbol>> {
+        let url = format!(
+            "{API_URL}/exchange-symbol-list/{exchange_short_code}?api_token={}&fmt=json&delisted=1",
+            self.api_token
+        );
+
+        Ok(self
+            .get_url::<Vec<Value>, _>(&url)
+            .await?
+            .into_iter()
+            .map(serde_json::from_value)
+            .filter_map(Result::ok)
+            .collect())
+    }
+
+    /**
+     * Tickers must be full codes, e.g. `AAPL.US`. Requests are chunked since the
+     * endpoint only accepts a handful of tickers at a time.
+     */
+    pub async fn get_real_time<S>(&self, tickers: &[S]) -> Result<Vec<Quote>>
+    where
+        S: Display,
+    {
+        let mut quotes = Vec::with_capacity(tickers.len());
+        let mut lower_time_limit = None;
+
+        for chunk in tickers.chunks(REAL_TIME_CHUNK) {
+            let (first, rest) = match chunk.split_first() {
+                Some(k) => k,
+                None => continue,
+            };
+            let rest = rest.iter().map(ToString::to_string).collect::<Vec<_>>();
+            let url = format!(
+                "{API_URL}/real-time/{first}?api_token={}&fmt=json&s={}",
+                self.api_token,
+                rest.join(",")
+            );
+
+            if let Some(lower_time_limit) = lower_time_limit {
+                if time::Instant::now() < lower_time_limit {
+                    time::sleep_until(lower_time_limit).await;
+                }
+            }
+
+            // A single ticker gives back an object, several give back an array
+            let values = match self.get_url::<Value, _>(&url).await? {
+                Value::Array(values) => values,
+                Value::Null => Vec::new(),
+                value => vec![value],
+            };
+            quotes.extend(
+                values
+                    .into_iter()
+                    .map(serde_json::from_value)
+                    .filter_map(Result::ok),
+            );
+            lower_time_limit = Some(time::Instant::now() + self.delay);
+        }
+
+        Ok(quotes)
+    }
+
+    pub async fn get_earnings_calendar(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<CalendarEarning>> {
+        self.get_calendar("earnings", from, to).await
+    }
+
+    pub async fn get_ipo_calendar(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<CalendarIpo>> {
+        self.get_calendar("ipos", from, to).await
+    }
+
+    pub async fn get_splits_calendar(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<CalendarSplit>> {
+        self.get_calendar("splits", from, to).await
+    }
+
+    /**
+     * Form 4 filings between `from` and `to`. Leaving out `code` gives back filings for every US
+     * ticker. Paged `INSIDER_TRANSACTIONS_LIMIT` rows at a time.
+     */
+    pub async fn get_insider_transactions(
+        &self,
+        code: Option<&str>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<InsiderTransaction>> {
+        let mut url = format!(
+            "{API_URL}/insider-transactions?api_token={}&fmt=json&from={}&to={}",
+            self.api_token,
+            from.format("%Y-%m-%d"),
+            to.format("%Y-%m-%d")
+        );
+        if let Some(code) = code {
+            url.push_str("&code=");
+            url.push_str(code);
+        }
+
+        self.get_pages(&url, INSIDER_TRANSACTIONS_LIMIT).await
+    }
+
+    /**
+     * The whole history of `indicator` (e.g. `gdp_current_usd`) for a country given as ISO3
+     */
+    pub async fn get_macro_indicator(
+        &self,
+        country_iso3: impl Display,
+        indicator: impl Display,
+    ) -> Result<Vec<MacroIndicator>> {
+        let url = format!(
+            "{API_URL}/macro-indicator/{country_iso3}?api_token={}&fmt=json&indicator={indicator}",
+            self.api_token
+        );
+
+        Ok(self
+            .get_url::<Vec<Value>, _>(&url)
+            .await?
+            .into_iter()
+            .map(serde_json::from_value)
+            .filter_map(Result::ok)
+            .collect())
+    }
+
+    /**
+     * Economic events for a country given as ISO2. Paged `ECONOMIC_EVENTS_LIMIT` rows at a time.
+     */
+    pub async fn get_economic_events(
+        &self,
+        country_iso2: impl Display,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<EconomicEvent>> {
+        let url = format!(
+            "{API_URL}/economic-events?api_token={}&fmt=json&country={country_iso2}&from={}&to={}",
+            self.api_token,
+            from.format("%Y-%m-%d"),
+            to.format("%Y-%m-%d")
+        );
+
+        self.get_pages(&url, ECONOMIC_EVENTS_LIMIT).await
+    }
+
+    pub async fn search(&self, query: impl Display, limit: usize) -> Result<Vec<SearchResult>> {
+        let url = format!(
+            "{API_URL}/search/{query}?api_token={}&fmt=json&limit={limit}",
+            self.api_token
+        );
+
+        Ok(self
+            .get_url::<Vec<Value>, _>(&url)
+            .await?
+            .into_iter()
+            .map(serde_json::from_value)
+            .filter_map(Result::ok)
+            .collect())
+    }
+
+    /**
+     * Every symbol matching `filters`, largest market cap first. Pages until the screener runs
+     * out of results or hits its offset limit, in which case only the largest are returned.
+     */
+    pub async fn screen(&self, filters: &ScreenerFilters) -> Result<Vec<ScreenerResult>> {
+        // The filters are JSON, with quotes and brackets that don't belong in a query string
+        let filters = filters.to_query();
+        let filters = utf8_percent_encode(&filters, NON_ALPHANUMERIC);
+        let mut results = Vec::new();
+        let mut offset = 0;
+
+        while offset <= SCREENER_MAX_OFFSET {
+            let url = format!(
+                "{API_URL}/screener?api_token={}&fmt=json&sort=market_capitalization.desc&limit={SCREENER_PAGE}&offset={offset}&filters={filters}",
+                self.api_token
+            );
+            let rows = match self.get_url::<Value, _>(&url).await? {
+                Value::Object(mut map) => map.remove("data").unwrap_or_default(),
+                _ => Value::Null,
+            };
+            let rows = match rows {
+                Value::Array(rows) => rows,
+                _ => Vec::new(),
+            };
+
+            let page_size = rows.len();
+            results.extend(
+                rows.into_iter()
+                    .map(serde_json::from_value)
+                    .filter_map(Result::ok),
+            );
+            if page_size < SCREENER_PAGE {
+                return Ok(results);
+            }
+            offset += SCREENER_PAGE;
+        }
+
+        eprintln!(
+            "[{}] ({}) The screener stops at offset {}, only the {} largest matches are used. Narrow the filters to get all of them",
+            "SCREENER".bold().yellow(),
+            "WARNING".yellow(),
+            SCREENER_MAX_OFFSET,
+            results.len()
+        );
+        Ok(results)
+    }
+
+    /**
+     * Every ticker that `identifier` maps to. The rows are wrapped in `{"data": [...]}`
+     */
+    pub async fn get_id_mapping(
+        &self,
+        kind: IdKind,
+        identifier: impl Display,
+    ) -> Result<Vec<IdMapping>> {
+        let url = format!(
+            "{API_URL}/id-mapping?api_token={}&fmt=json&filter[{}]={identifier}",
+            self.api_token,
+            kind.as_str()
+        );
+
+        let rows = match self.get_url::<Value, _>(&url).await? {
+            Value::Object(mut map) => map.remove("data").unwrap_or_default(),
+            _ => Value::Null,
+        };
+        let rows = match rows {
+            Value::Array(rows) => rows,
+            _ => Vec::new(),
+        };
+
+        Ok(rows
+            .into_iter()
+            .map(serde_json::from_value)
+            .filter_map(Result::ok)
+            .collect())
+    }
+
+    /**
+     * Every call and put of `ticker` (e.g. `AAPL.US`) expiring between `from` and `to`.
+     * Contracts come grouped per expiry as `{"data": [{"options": {"CALL": [...], "PUT": [...]}}]}`
+     */
+    pub async fn get_options(
+        &self,
+        ticker: impl Display,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<OptionContract>> {
+        let url = format!(
+            "{API_URL}/options/{ticker}?api_token={}&fmt=json&from={}&to={}",
+            self.api_token,
+            from.format("%Y-%m-%d"),
+            to.format("%Y-%m-%d")
+        );
+
+        let expiries = match self.get_url::<Value, _>(&url).await? {
+            Value::Object(mut map) => map.remove("data").unwrap_or_default(),
+            _ => Value::Null,
+        };
+        let expiries = match expiries {
+            Value::Array(expiries) => expiries,
+            _ => Vec::new(),
+        };
+
+        let mut contracts = Vec::new();
+        for mut expiry in expiries {
+            for side in ["CALL", "PUT"] {
+                if let Some(Value::Array(rows)) = expiry
+                    .get_mut("options")
+                    .and_then(|options| options.get_mut(side))
+                    .map(Value::take)
+                {
+                    contracts.extend(
+                        rows.into_iter()
+                            .map(serde_json::from_value)
+                            .filter_map(Result::ok),
+                    );
+                }
+            }
+        }
+
+        Ok(contracts)
+    }
+
+    /**
+     * Daily bars for `ticker`, e.g. `US10Y.GBOND`, from `from` (or the very beginning) until today
+     */
+    pub async fn get_eod(&self, ticker: impl Display, from: Option<NaiveDate>) -> Result<Vec<Eod>> {
+        let mut url = format!(
+            "{API_URL}/eod/{ticker}?api_token={}&fmt=json",
+            self.api_token
+        );
+        if let Some(from) = from {
+            url.push_str(&format!("&from={}", from.format("%Y-%m-%d")));
+        }
+
+        Ok(self
+            .get_url::<Vec<Value>, _>(&url)
+            .await?
+            .into_iter()
+            .map(serde_json::from_value)
+            .filter_map(Result::ok)
+            .collect())
+    }
+
+    /**
+     * Weekly market capitalization of `ticker` (e.g. `AAPL.US`) from `from` (or the very beginning).
+     * The points come as an object keyed by index, `{"0": {"date": ..., "value": ...}, ...}`
+     */
+    pub async fn get_historical_market_cap(
+        &self,
+        ticker: impl Display,
+        from: Option<NaiveDate>,
+    ) -> Result<Vec<MarketCap>> {
+        let mut url = format!(
+            "{API_URL}/historical-market-cap/{ticker}?api_token={}&fmt=json",
+            self.api_token
+        );
+        if let Some(from) = from {
+            url.push_str(&format!("&from={}", from.format("%Y-%m-%d")));
+        }
+
+        let points = match self.get_url::<Value, _>(&url).await? {
+            Value::Object(map) => map.into_iter().map(|(_, v)| v).collect(),
+            Value::Array(points) => points,
+            _ => Vec::new(),
+        };
+        let mut points = points
+            .into_iter()
+            .map(serde_json::from_value)
+            .filter_map(Result::ok)
+            .collect::<Vec<MarketCap>>();
+        points.sort_by_key(|x| x.date);
+
+        Ok(points)
+    }
+
+    /**
+     * Daily news sentiment of `ticker` (e.g. `AAPL.US`) from `from` (or as far back as there is).
+     * The days come keyed by ticker, `{"AAPL.US": [{"date": ..., "count": ..., "normalized": ...}]}`
+     */
+    pub async fn get_sentiment(
+        &self,
+        ticker: impl Display,
+        from: Option<NaiveDate>,
+    ) -> Result<Vec<DailySentiment>> {
+        let mut url = format!(
+            "{API_URL}/sentiments?s={ticker}&api_token={}&fmt=json",
+            self.api_token
+        );
+        if let Some(from) = from {
+            url.push_str(&format!("&from={}", from.format("%Y-%m-%d")));
+        }
+
+        let days = match self.get_url::<Value, _>(&url).await? {
+            Value::Object(map) => map
+                .into_iter()
+                .flat_map(|(_, v)| match v {
+                    Value::Array(days) => days,
+                    _ => Vec::new(),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let mut days = days
+            .into_iter()
+            .map(serde_json::from_value)
+            .filter_map(Result::ok)
+            .collect::<Vec<DailySentiment>>();
+        days.sort_by_key(|x| x.date);
+
+        Ok(days)
+    }
+
+    /**
+     * Will return None if the bond isn't known
+     */
+    pub async fn get_bond_fundamentals(
+        &self,
+        isin: impl Display,
+    ) -> Result<Option<BondFundamental>> {
+        let url = format!(
+            "{API_URL}/bond-fundamentals/{isin}?api_token={}&fmt=json",
+            self.api_token
+        );
+
+        Ok(serde_json::from_value(self.get_url::<Value, _>(&url).await?).ok())
+    }
+
+    /**
+     * The calendar endpoints wrap their rows in an object keyed by the calendar kind,
+     * e.g. `{"type": "Earnings", ..., "earnings": [...]}`
+     */
+    async fn get_calendar<D>(&self, kind: &str, from: NaiveDate, to: NaiveDate) -> Result<Vec<D>>
+    where
+        D: DeserializeOwned,
+    {
+        let url = format!(
+            "{API_URL}/calendar/{kind}?api_token={}&fmt=json&from={}&to={}",
+            self.api_token,
+            from.format("%Y-%m-%d"),
+            to.format("%Y-%m-%d")
+        );
+
+        let rows = match self.get_url::<Value, _>(&url).await? {
+            Value::Object(mut map) => map.remove(kind).unwrap_or_default(),
+            _ => Value::Null,
+        };
+        let rows = match rows {
+            Value::Array(rows) => rows,
+            _ => Vec::new(),
+        };
+
+        Ok(rows
+            .into_iter()
+            .map(serde_json::from_value)
+            .filter_map(Result::ok)
+            .collect())
+    }
+
+    /**
+     * Every row of `url`, fetched `limit` at a time with `offset` until a page comes back short.
+     * Rows that don't deserialize are skipped but still count towards the page. Fails rather
+     * than silently losing rows if the same page comes back twice, i.e. `offset` is ignored.
+     */
+    async fn get_pages<D>(&self, url: &str, limit: usize) -> Result<Vec<D>>
+    where
+        D: DeserializeOwned,
+    {
+        let mut rows = Vec::new();
+        let mut previous_first = None;
+        for offset in (0..).step_by(limit) {
+            let page_url = format!("{url}&limit={limit}&offset={offset}");
+            let page = self.get_url::<Vec<Value>, _>(&page_url).await?;
+            if !page.is_empty() && page.first() == previous_first.as_ref() {
+                return Err(anyhow!(
+                    "Got the same page twice at offset {offset}, more than {limit} rows can't be fetched"
+                ));
+            }
+            previous_first = page.first().cloned();
+
+            let page_size = page.len();
+            rows.extend(
+                page.into_iter()
+                    .map(serde_json::from_value)
+                    .filter_map(Result::ok),
+            );
+            if page_size < limit {
+                break;
+            }
+        }
+        Ok(rows)
+    }
+
     /**
      * Will return Default::default() if 404 is gotten
      */
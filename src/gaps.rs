@@ -0,0 +1,143 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::trading_calendar::TradingCalendar;
+
+/// A run of sessions without a single stored bar, `from` and `to` inclusive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gap {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub sessions: usize,
+}
+
+impl Gap {
+    /// The `max_from_date` and `to_date` to download the gap with: the open of `from` and the close of `to`
+    pub fn bounds(&self, calendar: &TradingCalendar) -> (DateTime<Utc>, DateTime<Utc>) {
+        (
+            calendar.session_bounds(self.from).0,
+            calendar.session_bounds(self.to).1,
+        )
+    }
+}
+
+/**
+ * The sessions between the first and last of `days` that have no bars. `days` are the local dates
+ * with bars, oldest first. Days `is_session` says the exchange was closed don't end a gap.
+ */
+pub fn find_gaps(days: &[NaiveDate], is_session: impl Fn(NaiveDate) -> bool) -> Vec<Gap> {
+    let (Some(first), Some(last)) = (days.first(), days.last()) else {
+        return Vec::new();
+    };
+
+    let mut gaps = Vec::new();
+    let mut current: Option<Gap> = None;
+    let mut days = days.iter().peekable();
+    for date in first.iter_days().take_while(|x| x <= last) {
+        if days.next_if_eq(&&date).is_some() {
+            gaps.extend(current.take());
+        } else if is_session(date) {
+            match current.as_mut() {
+                Some(gap) => {
+                    gap.to = date;
+                    gap.sessions += 1;
+                }
+                None => {
+                    current = Some(Gap {
+                        from: date,
+                        to: date,
+                        sessions: 1,
+                    })
+                }
+            }
+        }
+    }
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    fn at(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        date(month, day)
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    fn us_gaps(days: &[NaiveDate]) -> Vec<Gap> {
+        let us = TradingCalendar::fallback("US");
+        find_gaps(days, |date| us.is_session(date))
+    }
+
+    #[test]
+    fn nothing_to_find_without_bars() {
+        assert!(us_gaps(&[]).is_empty());
+        assert!(us_gaps(&[date(7, 1)]).is_empty());
+        assert!(us_gaps(&[date(7, 1), date(7, 2)]).is_empty());
+    }
+
+    #[test]
+    fn single_sessions_are_gaps() {
+        let gaps = us_gaps(&[date(7, 1), date(7, 3)]);
+        assert_eq!(
+            gaps,
+            [Gap {
+                from: date(7, 2),
+                to: date(7, 2),
+                sessions: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn closed_days_only_pad_a_gap() {
+        // Friday to Friday, over a weekend and Independence Day
+        let gaps = us_gaps(&[date(6, 28), date(7, 12)]);
+        assert_eq!(
+            gaps,
+            [Gap {
+                from: date(7, 1),
+                to: date(7, 11),
+                sessions: 8,
+            }]
+        );
+
+        // Nothing missing but the holiday and the weekend around it
+        assert!(us_gaps(&[date(7, 3), date(7, 5), date(7, 8)]).is_empty());
+
+        let gaps = us_gaps(&[date(7, 1), date(7, 3), date(7, 5), date(7, 9)]);
+        let missing = gaps.iter().map(|x| (x.from, x.to)).collect::<Vec<_>>();
+        assert_eq!(
+            missing,
+            [(date(7, 2), date(7, 2)), (date(7, 8), date(7, 8))]
+        );
+    }
+
+    #[test]
+    fn bounds_are_the_sessions_in_utc() {
+        let gap = Gap {
+            from: date(7, 2),
+            to: date(7, 3),
+            sessions: 2,
+        };
+        // New York is behind UTC
+        let us = TradingCalendar::fallback("US");
+        assert_eq!(gap.bounds(&us), (at(7, 2, 13, 30), at(7, 3, 20, 0)));
+
+        // Sydney is ahead, and its sessions start the evening before in UTC
+        let sydney = TradingCalendar {
+            open: NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+            close: NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+            open_utc: NaiveTime::from_hms_opt(23, 0, 0).unwrap(),
+            close_utc: NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+            ..TradingCalendar::fallback("AU")
+        };
+        assert_eq!(gap.bounds(&sydney), (at(7, 1, 23, 0), at(7, 3, 5, 0)));
+    }
+}
@@ -2,4 +2,13 @@ pub mod eodhd;
 pub mod db;
 pub mod dump_routines;
 pub mod models;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod technicals;
+pub mod storage;
+pub mod postgres;
+pub mod sqlite;
+pub mod bars;
+pub mod validate;
+pub mod gaps;
+pub mod trading_calendar;
+pub mod partitions;
\ No newline at end of file
@@ -1,4 +1,4 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -15,7 +15,9 @@ pub struct Intraday {
     pub high: f64,
     pub low: f64,
     pub close: f64,
-    pub volume: i64,
+    /// Missing for instruments without volume, like currencies and indices
+    #[serde(default)]
+    pub volume: Option<i64>,
 }
 fn deserialize_datetime<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
 where
@@ -52,3 +54,566 @@ pub struct ExchangeSymbol {
     #[serde(rename = "Isin")]
     pub isin: Option<Box<str>>,
 }
+
+/// A live (or 15-20 minute delayed) quote from the real-time endpoint.
+/// EODHD sends `"NA"` for fields it doesn't have yet, which ends up as `None`.
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Quote {
+    /// Full ticker, e.g. `AAPL.US`
+    pub code: Box<str>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub timestamp: Option<i64>,
+    #[serde(rename = "gmtoffset")]
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub gmt_offset: i32,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub open: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub high: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub low: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub close: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub volume: Option<i64>,
+    #[serde(rename = "previousClose")]
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub previous_close: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub change: Option<f64>,
+    #[serde(rename = "change_p")]
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub change_percent: Option<f64>,
+}
+
+impl Quote {
+    pub fn code_and_exchange(&self) -> (&str, &str) {
+        split_code(&self.code)
+    }
+}
+
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CalendarEarning {
+    /// Full ticker, e.g. `AAPL.US`
+    pub code: Box<str>,
+    pub report_date: NaiveDate,
+    /// End of the fiscal period being reported
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    #[serde(default)]
+    pub date: Option<NaiveDate>,
+    pub before_after_market: Option<Box<str>>,
+    pub currency: Option<Box<str>>,
+    pub actual: Option<f64>,
+    pub estimate: Option<f64>,
+    pub difference: Option<f64>,
+    pub percent: Option<f64>,
+}
+
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CalendarIpo {
+    /// Full ticker, e.g. `RDDT.US`
+    pub code: Box<str>,
+    pub name: Option<Box<str>>,
+    /// Exchange name as reported by EODHD, e.g. `NYSE`
+    pub exchange: Option<Box<str>>,
+    pub currency: Option<Box<str>>,
+    /// Missing for IPOs that are expected but not scheduled yet
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    #[serde(default)]
+    pub start_date: Option<NaiveDate>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    #[serde(default)]
+    pub filing_date: Option<NaiveDate>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    #[serde(default)]
+    pub amended_date: Option<NaiveDate>,
+    pub price_from: Option<f64>,
+    pub price_to: Option<f64>,
+    pub offer_price: Option<f64>,
+    pub shares: Option<i64>,
+    pub deal_type: Option<Box<str>>,
+}
+
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CalendarSplit {
+    /// Full ticker, e.g. `NVDA.US`
+    pub code: Box<str>,
+    pub split_date: NaiveDate,
+    pub optionable: Option<Box<str>>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    #[serde(default)]
+    pub old_shares: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    #[serde(default)]
+    pub new_shares: Option<f64>,
+}
+
+/// Splits `AAPL.US` into `("AAPL", "US")`. Codes without an exchange suffix get an empty exchange.
+pub fn split_code(code: &str) -> (&str, &str) {
+    code.rsplit_once('.').unwrap_or((code, ""))
+}
+
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InsiderTransaction {
+    pub code: Box<str>,
+    pub exchange: Box<str>,
+    /// Filing date
+    pub date: NaiveDate,
+    pub owner_name: Option<Box<str>>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    #[serde(default)]
+    pub transaction_date: Option<NaiveDate>,
+    pub transaction_code: Option<Box<str>>,
+    pub transaction_amount: Option<f64>,
+    pub transaction_price: Option<f64>,
+    pub transaction_acquired_disposed: Option<Box<str>>,
+    pub post_transaction_amount: Option<f64>,
+    pub sec_link: Option<Box<str>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MacroIndicator {
+    /// ISO3, e.g. `USA`
+    #[serde(rename = "CountryCode")]
+    pub country_code: Box<str>,
+    /// Human readable name, e.g. `GDP (current US$)`
+    #[serde(rename = "Indicator")]
+    pub indicator: Box<str>,
+    #[serde(rename = "Date")]
+    pub date: NaiveDate,
+    #[serde(rename = "Period")]
+    pub period: Option<Box<str>>,
+    #[serde(rename = "Value")]
+    pub value: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EconomicEvent {
+    #[serde(rename = "type")]
+    pub event_type: Box<str>,
+    pub comparison: Option<Box<str>>,
+    pub period: Option<Box<str>>,
+    /// ISO2, e.g. `US`
+    pub country: Box<str>,
+    #[serde(
+        deserialize_with = "deserialize_datetime",
+        serialize_with = "serialize_datetime"
+    )]
+    pub date: DateTime<Utc>,
+    pub actual: Option<f64>,
+    pub previous: Option<f64>,
+    pub estimate: Option<f64>,
+    pub change: Option<f64>,
+    pub change_percentage: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchResult {
+    #[serde(rename = "Code")]
+    pub code: Box<str>,
+    #[serde(rename = "Exchange")]
+    pub exchange: Box<str>,
+    #[serde(rename = "Name")]
+    pub name: Option<Box<str>>,
+    #[serde(rename = "Type")]
+    pub symbol_type: Option<Box<str>>,
+    #[serde(rename = "Country")]
+    pub country: Option<Box<str>>,
+    #[serde(rename = "Currency")]
+    pub currency: Option<Box<str>>,
+    #[serde(rename = "ISIN")]
+    pub isin: Option<Box<str>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IdMapping {
+    /// Full ticker, e.g. `AAPL.US`
+    pub symbol: Box<str>,
+    pub isin: Option<Box<str>>,
+    pub figi: Option<Box<str>>,
+    pub cusip: Option<Box<str>>,
+}
+
+/// The kinds of identifiers that can be resolved to a (code, exchange)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdKind {
+    Isin,
+    Cusip,
+    Figi,
+}
+
+impl IdKind {
+    /// Guesses the kind from the shape of the identifier. Plain tickers give `None`
+    pub fn detect(identifier: &str) -> Option<Self> {
+        let alphanumeric = identifier.chars().all(|c| c.is_ascii_alphanumeric());
+        let last_is_digit = identifier.ends_with(|c: char| c.is_ascii_digit());
+        match identifier.len() {
+            12 if alphanumeric && identifier.starts_with("BBG") => Some(Self::Figi),
+            12 if alphanumeric
+                && last_is_digit
+                && identifier[..2].chars().all(|c| c.is_ascii_uppercase()) =>
+            {
+                Some(Self::Isin)
+            }
+            9 if alphanumeric && has_cusip_check_digit(identifier) => Some(Self::Cusip),
+            _ => None,
+        }
+    }
+
+    /// The name used by the id-mapping endpoint and `CrossReferencedSymbols`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Isin => "isin",
+            Self::Cusip => "cusip",
+            Self::Figi => "figi",
+        }
+    }
+}
+
+/**
+ * Whether the last of 9 characters is the CUSIP check digit of the first 8. CINS numbers, e.g.
+ * `G5960L103`, are CUSIPs starting with a letter and check out the same way.
+ */
+fn has_cusip_check_digit(identifier: &str) -> bool {
+    let mut chars = identifier.chars();
+    let check = chars.next_back().and_then(|c| c.to_digit(10));
+    let sum = chars.enumerate().try_fold(0, |sum, (i, c)| {
+        let value = c.to_digit(36)?;
+        let value = if i % 2 == 1 { value * 2 } else { value };
+        Some(sum + value / 10 + value % 10)
+    });
+    matches!((sum, check), (Some(sum), Some(check)) if (10 - sum % 10) % 10 == check)
+}
+
+/// The static part of an option, one per contract
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OptionContract {
+    /// OCC symbol, e.g. `AAPL240119C00190000`
+    pub contract_name: Box<str>,
+    /// `CALL` or `PUT`
+    #[serde(rename = "type")]
+    pub option_type: Box<str>,
+    pub strike: f64,
+    pub expiration_date: NaiveDate,
+    pub contract_size: Option<Box<str>>,
+    pub currency: Option<Box<str>>,
+    #[serde(flatten)]
+    pub snapshot: OptionSnapshot,
+}
+
+/// The daily changing part of an option
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OptionSnapshot {
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub last_price: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub bid: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub ask: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub change: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub change_percent: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub volume: Option<i64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub open_interest: Option<i64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub implied_volatility: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub delta: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub gamma: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub theta: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub vega: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub rho: Option<f64>,
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub theoretical: Option<f64>,
+}
+
+/// Decides which suffix a symbol is requested with and how its prices are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetClass {
+    Equity,
+    Forex,
+    Crypto,
+    Index,
+}
+
+impl AssetClass {
+    /// EODHD lists both currencies and crypto as `Currency`, only the exchange tells them apart
+    pub fn of(symbol: &ExchangeSymbol) -> Self {
+        match (symbol.symbol_type.as_ref(), symbol.exchange.as_ref()) {
+            (_, "CC") => Self::Crypto,
+            ("Currency", _) | (_, "FOREX") => Self::Forex,
+            ("INDEX", _) | (_, "INDX") => Self::Index,
+            _ => Self::Equity,
+        }
+    }
+
+    /// The exchange part of `CODE.EXCHANGE`. Equities use the exchange they were listed from
+    pub fn suffix<'a>(&self, exchange_short_code: &'a str) -> &'a str {
+        match self {
+            Self::Equity => exchange_short_code,
+            Self::Forex => "FOREX",
+            Self::Crypto => "CC",
+            Self::Index => "INDX",
+        }
+    }
+}
+
+/// A daily bar from the end-of-day endpoint
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Eod {
+    pub date: NaiveDate,
+    pub open: Option<f64>,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub close: Option<f64>,
+    pub adjusted_close: Option<f64>,
+    #[serde(default)]
+    pub volume: Option<i64>,
+}
+
+/// EODHD sends most numbers of bond fundamentals as strings, e.g. `"Coupon": "5.000"`
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BondFundamental {
+    #[serde(rename = "ISIN")]
+    pub isin: Box<str>,
+    #[serde(rename = "CUSIP")]
+    pub cusip: Option<Box<str>>,
+    #[serde(rename = "Name")]
+    pub name: Option<Box<str>>,
+    #[serde(rename = "Currency")]
+    pub currency: Option<Box<str>>,
+    #[serde(rename = "Type")]
+    pub bond_type: Option<Box<str>>,
+    #[serde(rename = "Coupon", default)]
+    #[serde_as(
+        as = "serde_with::DefaultOnError<Option<serde_with::PickFirst<(_, serde_with::DisplayFromStr)>>>"
+    )]
+    pub coupon: Option<f64>,
+    #[serde(rename = "Price", default)]
+    #[serde_as(
+        as = "serde_with::DefaultOnError<Option<serde_with::PickFirst<(_, serde_with::DisplayFromStr)>>>"
+    )]
+    pub price: Option<f64>,
+    #[serde(rename = "YieldToMaturity", default)]
+    #[serde_as(
+        as = "serde_with::DefaultOnError<Option<serde_with::PickFirst<(_, serde_with::DisplayFromStr)>>>"
+    )]
+    pub yield_to_maturity: Option<f64>,
+    #[serde(rename = "Maturity_Date", default)]
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub maturity_date: Option<NaiveDate>,
+    #[serde(rename = "Callable")]
+    pub callable: Option<Box<str>>,
+    #[serde(rename = "IssueData", default)]
+    pub issue_data: Option<BondIssueData>,
+}
+
+#[serde_with::serde_as]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BondIssueData {
+    #[serde(rename = "IssueDate", default)]
+    #[serde_as(as = "serde_with::DefaultOnError")]
+    pub issue_date: Option<NaiveDate>,
+    #[serde(rename = "Issuer")]
+    pub issuer: Option<Box<str>>,
+    #[serde(rename = "IssuerCountry")]
+    pub issuer_country: Option<Box<str>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MarketCap {
+    pub date: NaiveDate,
+    pub value: f64,
+}
+
+/// The account behind the API token, from the `user` endpoint
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserInfo {
+    pub name: Option<Box<str>>,
+    pub subscription_type: Option<Box<str>>,
+    /// Calls used on `api_requests_date`
+    pub api_requests: u64,
+    pub api_requests_date: Option<NaiveDate>,
+    pub daily_rate_limit: u64,
+    pub extra_limit: u64,
+    #[serde(alias = "subscriptionEndDate", alias = "expirationDate")]
+    pub subscription_expires: Option<Box<str>>,
+}
+
+impl UserInfo {
+    /// Calls left today. The counter belongs to an earlier day if `api_requests_date` isn't today
+    pub fn remaining_calls(&self, today: NaiveDate) -> u64 {
+        let used = match self.api_requests_date {
+            Some(date) if date < today => 0,
+            _ => self.api_requests,
+        };
+        (self.daily_rate_limit + self.extra_limit).saturating_sub(used)
+    }
+}
+
+/// Sentiment aggregated over the day's news, `normalized` being between -1 and 1
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DailySentiment {
+    pub date: NaiveDate,
+    pub count: u32,
+    pub normalized: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ScreenerResult {
+    pub code: Box<str>,
+    pub name: Option<Box<str>>,
+    pub exchange: Box<str>,
+    pub sector: Option<Box<str>>,
+    pub industry: Option<Box<str>>,
+    pub market_capitalization: Option<f64>,
+    pub avgvol_200d: Option<f64>,
+}
+
+/// Filters for the screener. Everything left as `None` is not filtered on
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ScreenerFilters {
+    pub exchange: Option<String>,
+    pub sector: Option<String>,
+    pub min_market_cap: Option<f64>,
+    /// Average daily volume over the last 200 days
+    pub min_volume: Option<f64>,
+}
+
+impl ScreenerFilters {
+    /// The `filters` query parameter, e.g. `[["exchange","=","us"],["market_capitalization",">",1000]]`
+    pub fn to_query(&self) -> String {
+        let mut filters = Vec::new();
+        if let Some(exchange) = &self.exchange {
+            filters.push(serde_json::json!([
+                "exchange",
+                "=",
+                exchange.to_lowercase()
+            ]));
+        }
+        if let Some(sector) = &self.sector {
+            filters.push(serde_json::json!(["sector", "=", sector]));
+        }
+        if let Some(min_market_cap) = self.min_market_cap {
+            filters.push(serde_json::json!([
+                "market_capitalization",
+                ">",
+                min_market_cap
+            ]));
+        }
+        if let Some(min_volume) = self.min_volume {
+            filters.push(serde_json::json!(["avgvol_200d", ">", min_volume]));
+        }
+        serde_json::Value::Array(filters).to_string()
+    }
+}
+
+/// An exchange from the exchange-details endpoint. `holidays` is filled in by `Eodhd`, since EODHD
+/// sends them as an object keyed by index
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct ExchangeDetails {
+    pub code: Box<str>,
+    pub timezone: Option<Box<str>>,
+    pub trading_hours: TradingHours,
+    #[serde(skip)]
+    pub holidays: Vec<ExchangeHoliday>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct TradingHours {
+    pub open: Option<NaiveTime>,
+    pub close: Option<NaiveTime>,
+    #[serde(rename = "OpenUTC")]
+    pub open_utc: Option<NaiveTime>,
+    #[serde(rename = "CloseUTC")]
+    pub close_utc: Option<NaiveTime>,
+    /// E.g. `Mon,Tue,Wed,Thu,Fri`
+    pub working_days: Option<Box<str>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ExchangeHoliday {
+    pub holiday: Box<str>,
+    pub date: NaiveDate,
+    /// `official` when the exchange is closed, `bank` when only the banks are
+    #[serde(rename = "Type", default)]
+    pub kind: Option<Box<str>>,
+}
+
+impl ExchangeHoliday {
+    /// Holidays without a type are taken to close the exchange
+    pub fn is_closure(&self) -> bool {
+        self.kind
+            .as_deref()
+            .is_none_or(|kind| kind.eq_ignore_ascii_case("official"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_identifiers() {
+        assert_eq!(IdKind::detect("US0378331005"), Some(IdKind::Isin));
+        assert_eq!(IdKind::detect("BBG000B9XRY4"), Some(IdKind::Figi));
+        assert_eq!(IdKind::detect("037833100"), Some(IdKind::Cusip));
+        assert_eq!(IdKind::detect("G5960L103"), Some(IdKind::Cusip));
+        assert_eq!(IdKind::detect("037833101"), None);
+        assert_eq!(IdKind::detect("AAPL"), None);
+        assert_eq!(IdKind::detect("GOOGLEINC"), None);
+    }
+
+    #[test]
+    fn only_official_holidays_close() {
+        let holidays: Vec<ExchangeHoliday> = serde_json::from_str(
+            r#"[
+                {"Holiday": "Thanksgiving Day", "Date": "2024-11-28", "Type": "official"},
+                {"Holiday": "Veterans Day", "Date": "2024-11-11", "Type": "bank"},
+                {"Holiday": "Christmas Day", "Date": "2024-12-25"}
+            ]"#,
+        )
+        .unwrap();
+        let closures = holidays
+            .iter()
+            .filter(|x| x.is_closure())
+            .map(|x| x.holiday.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(closures, ["Thanksgiving Day", "Christmas Day"]);
+    }
+
+    #[test]
+    fn quotes_without_data_still_parse() {
+        let quote: Quote = serde_json::from_str(
+            r#"{"code": "XYZ.US", "timestamp": "NA", "gmtoffset": "NA", "open": "NA", "close": 1.5}"#,
+        )
+        .unwrap();
+        assert_eq!(quote.timestamp, None);
+        assert_eq!(quote.gmt_offset, 0);
+        assert_eq!(quote.open, None);
+        assert_eq!(quote.close, Some(1.5));
+    }
+}
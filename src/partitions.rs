@@ -0,0 +1,230 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Months, NaiveDate, NaiveDateTime};
+use sqlx::FromRow;
+use std::fmt::Display;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Catches everything after the last month, so inserts never fail for lack of a partition
+pub const FUTURE_PARTITION: &str = "pfuture";
+
+/// `p202403` holds March 2024
+pub fn partition_name(month: NaiveDate) -> String {
+    format!("p{}", month.format("%Y%m"))
+}
+
+/// The first of the month a partition holds, None for `pfuture`
+pub fn partition_month(name: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(&format!("{}01", name.strip_prefix('p')?), "%Y%m%d").ok()
+}
+
+pub fn first_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap()
+}
+
+pub fn next_month(month: NaiveDate) -> NaiveDate {
+    month + Months::new(1)
+}
+
+/// What a month's partition is bounded by: the UNIX timestamp the next month starts at
+pub fn partition_bound(month: NaiveDate) -> i64 {
+    next_month(month)
+        .and_time(Default::default())
+        .and_utc()
+        .timestamp()
+}
+
+/// The first of every month from `from` to `to`, both inclusive
+pub fn months_between(from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+    std::iter::successors(Some(first_of_month(from)), |x| Some(next_month(*x)))
+        .take_while(|x| *x <= to)
+        .collect()
+}
+
+/// Whether `month` is past a retention of `months` counted back from `this_month`. Without a
+/// retention nothing expires
+pub fn is_expired(month: NaiveDate, months: Option<u32>, this_month: NaiveDate) -> bool {
+    months.is_some_and(|months| month < this_month - Months::new(months))
+}
+
+/// How many whole months of intraday prices an exchange keeps. Parsed from e.g. `US=24`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Retention {
+    pub exchange: Box<str>,
+    pub months: u32,
+}
+
+impl FromStr for Retention {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (exchange, months) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected EXCHANGE=MONTHS, e.g. US=24, got '{s}'"))?;
+        let months = months
+            .parse()
+            .map_err(|_| anyhow!("Invalid number of months in '{s}'"))?;
+        Ok(Self {
+            exchange: exchange.to_uppercase().into(),
+            months,
+        })
+    }
+}
+
+/// A partition of `StockPrice`. `rows` is InnoDB's estimate
+#[derive(Debug, Clone, FromRow)]
+pub struct Partition {
+    pub name: Option<Box<str>>,
+    pub rows: Option<u64>,
+}
+
+/// A row of `StockPrice` as it's written to an archive
+#[derive(Debug, FromRow)]
+pub struct ArchivedPrice {
+    pub code: Box<str>,
+    pub exchange: Box<str>,
+    pub timestamp: Option<NaiveDateTime>,
+    pub gmtoffset: Option<i32>,
+    pub open: Option<f64>,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub close: Option<f64>,
+    pub volume: Option<i64>,
+}
+
+impl ArchivedPrice {
+    pub const CSV_HEADER: &'static str =
+        "code,exchange,timestamp,gmtoffset,open,high,low,close,volume";
+
+    /// Codes never contain commas or quotes, so nothing needs quoting. NULLs are left empty
+    pub fn write_csv(&self, out: &mut impl Write) -> std::io::Result<()> {
+        fn field(x: Option<impl Display>) -> String {
+            x.map(|x| x.to_string()).unwrap_or_default()
+        }
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            self.code,
+            self.exchange,
+            field(self.timestamp.map(|x| x.format("%Y-%m-%d %H:%M:%S"))),
+            field(self.gmtoffset),
+            field(self.open),
+            field(self.high),
+            field(self.low),
+            field(self.close),
+            field(self.volume)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn month(year: i32, month: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, 1).unwrap()
+    }
+
+    fn timestamp(date: NaiveDate, hour: u32, minute: u32, second: u32) -> i64 {
+        date.and_hms_opt(hour, minute, second)
+            .unwrap()
+            .and_utc()
+            .timestamp()
+    }
+
+    #[test]
+    fn partitions_are_bounded_by_the_next_month() {
+        assert_eq!(partition_bound(month(2020, 10)), 1604188800);
+        assert_eq!(partition_bound(month(2023, 12)), 1704067200);
+        assert_eq!(partition_bound(month(2024, 2)), 1709251200);
+
+        // The last second of a month stays in its partition, midnight UTC starts the next one
+        let october = month(2020, 10);
+        let last = NaiveDate::from_ymd_opt(2020, 10, 31).unwrap();
+        assert!(timestamp(last, 23, 59, 59) < partition_bound(october));
+        assert_eq!(
+            timestamp(month(2020, 11), 0, 0, 0),
+            partition_bound(october)
+        );
+    }
+
+    #[test]
+    fn months_between_includes_both_ends() {
+        let from = NaiveDate::from_ymd_opt(2023, 11, 15).unwrap();
+        let to = NaiveDate::from_ymd_opt(2024, 2, 10).unwrap();
+        assert_eq!(
+            months_between(from, to),
+            [
+                month(2023, 11),
+                month(2023, 12),
+                month(2024, 1),
+                month(2024, 2)
+            ]
+        );
+        assert_eq!(months_between(from, from), [month(2023, 11)]);
+        assert!(months_between(month(2024, 4), month(2024, 3)).is_empty());
+    }
+
+    #[test]
+    fn partition_names_round_trip() {
+        assert_eq!(partition_name(month(2024, 3)), "p202403");
+        assert_eq!(partition_month("p202403"), Some(month(2024, 3)));
+        assert_eq!(partition_month(FUTURE_PARTITION), None);
+        assert_eq!(partition_month("p202413"), None);
+    }
+
+    #[test]
+    fn retention_is_parsed() {
+        assert_eq!(
+            "us=24".parse::<Retention>().unwrap(),
+            Retention {
+                exchange: "US".into(),
+                months: 24,
+            }
+        );
+        assert!("US".parse::<Retention>().is_err());
+        assert!("US=two".parse::<Retention>().is_err());
+        assert!("US=-1".parse::<Retention>().is_err());
+    }
+
+    #[test]
+    fn months_expire_after_the_retention() {
+        let this_month = month(2024, 3);
+        assert!(!is_expired(month(2022, 3), Some(24), this_month));
+        assert!(is_expired(month(2022, 2), Some(24), this_month));
+        assert!(!is_expired(this_month, Some(0), this_month));
+        assert!(is_expired(month(2024, 2), Some(0), this_month));
+        assert!(!is_expired(month(2000, 1), None, this_month));
+    }
+
+    #[test]
+    fn archived_prices_leave_nulls_empty() {
+        let mut price = ArchivedPrice {
+            code: "AAPL".into(),
+            exchange: "US".into(),
+            timestamp: month(2024, 3).and_hms_opt(14, 30, 0),
+            gmtoffset: Some(-5),
+            open: Some(1.5),
+            high: Some(2.0),
+            low: Some(1.0),
+            close: Some(1.25),
+            volume: Some(100),
+        };
+        let mut out = Vec::new();
+        price.write_csv(&mut out).unwrap();
+
+        price.timestamp = None;
+        price.gmtoffset = None;
+        price.open = None;
+        price.high = None;
+        price.low = None;
+        price.close = None;
+        price.volume = None;
+        price.write_csv(&mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "AAPL,US,2024-03-01 14:30:00,-5,1.5,2,1,1.25,100\nAAPL,US,,,,,,,\n"
+        );
+    }
+}
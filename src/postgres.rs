@@ -0,0 +1,427 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::postgres::PgRow;
+use sqlx::{Pool, Postgres, Row};
+use std::sync::Arc;
+
+use crate::db::{
+    diff_listing, ChangeType, Db, OutdatedSymbolPrice, StoredExchangeSymbol, SymbolListDiff,
+};
+use crate::models::{ExchangeSymbol, Intraday};
+use crate::storage::{PoolSettings, Storage};
+use crate::validate::{gate, PriceRow, PriceTable, Rule, Validator};
+
+/**
+ * PostgreSQL, optionally with TimescaleDB, set up with `schema.postgres.sql`.
+ * Identifiers are unquoted there, so Postgres folds them to lower case.
+ */
+pub struct PgDb {
+    pool: Pool<Postgres>,
+    gate: Option<Validator>,
+}
+
+impl PgDb {
+    pub async fn connect(url: &str, settings: &PoolSettings) -> sqlx::Result<Self> {
+        let pool = settings.pool_options().connect(url).await?;
+        Ok(Self {
+            pool,
+            gate: settings.gate.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for PgDb {
+    async fn push_intraday(
+        &self,
+        code: &str,
+        exchange: &str,
+        intraday_prices: &[Intraday],
+    ) -> Result<()> {
+        let (intraday_prices, rejected) = gate(self.gate.as_ref(), code, exchange, intraday_prices);
+        let mut transaction = self.pool.begin().await?;
+
+        for (row, rule) in &rejected {
+            push_quarantined(&mut transaction, code, exchange, row, *rule).await?;
+        }
+
+        for intraday in intraday_prices {
+            sqlx::query(
+                "INSERT INTO StockPrice (code, exchange, timestamp, gmtoffset, open, high, low, close, volume)
+                 VALUES ($1, $2, to_timestamp($3) AT TIME ZONE 'UTC', $4, $5, $6, $7, $8, $9)
+                 ON CONFLICT DO NOTHING",
+            )
+            .bind(code)
+            .bind(exchange)
+            .bind(intraday.timestamp)
+            .bind(intraday.gmt_offset)
+            .bind(intraday.open)
+            .bind(intraday.high)
+            .bind(intraday.low)
+            .bind(intraday.close)
+            .bind(intraday.volume)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn push_exchange_symbols(
+        &self,
+        exchange_short_code: &str,
+        symbols: Vec<ExchangeSymbol>,
+    ) -> Result<SymbolListDiff> {
+        let mut transaction = self.pool.begin().await?;
+
+        let stored = sqlx::query_as::<_, StoredExchangeSymbol>(
+            r#"SELECT code, name, type, country, currency, isin, realExchange AS "realExchange", delisted
+               FROM ExchangeSymbol
+               WHERE exchange = $1"#,
+        )
+        .bind(exchange_short_code)
+        .fetch_all(&mut *transaction)
+        .await?;
+        let listing = diff_listing(stored, symbols);
+
+        for (code, (change_type, field, old_value, new_value)) in listing.changes {
+            push_symbol_change(
+                &mut transaction,
+                &code,
+                exchange_short_code,
+                change_type,
+                field,
+                old_value,
+                new_value,
+            )
+            .await?;
+        }
+
+        for symbol in &listing.symbols {
+            sqlx::query(
+                "INSERT INTO ExchangeSymbol (name, code, exchange, type, country, currency, isin, realExchange, delisted, firstSeen, lastSeen)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, FALSE, CURRENT_DATE, CURRENT_DATE)
+                 ON CONFLICT (code, exchange) DO UPDATE SET
+                    name = EXCLUDED.name, type = EXCLUDED.type, country = EXCLUDED.country,
+                    currency = EXCLUDED.currency, isin = EXCLUDED.isin, realExchange = EXCLUDED.realExchange,
+                    delisted = FALSE, lastSeen = CURRENT_DATE",
+            )
+            .bind(&symbol.name)
+            .bind(&symbol.code)
+            .bind(exchange_short_code)
+            .bind(&symbol.symbol_type)
+            .bind(&symbol.country)
+            .bind(&symbol.currency)
+            .bind(&symbol.isin)
+            .bind(symbol.exchange.as_ref())
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for code in &listing.removed {
+            sqlx::query(
+                "UPDATE ExchangeSymbol SET delisted = TRUE WHERE code = $1 AND exchange = $2",
+            )
+            .bind(code)
+            .bind(exchange_short_code)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(listing.diff)
+    }
+
+    async fn push_delisted_exchange_symbols(
+        &self,
+        exchange_short_code: &str,
+        symbols: Vec<ExchangeSymbol>,
+    ) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+
+        for symbol in symbols {
+            sqlx::query(
+                "INSERT INTO ExchangeSymbol (name, code, exchange, type, country, currency, isin, realExchange, delisted)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, TRUE)
+                 ON CONFLICT (code, exchange) DO UPDATE SET
+                    delisted = CASE WHEN ExchangeSymbol.lastSeen = CURRENT_DATE THEN ExchangeSymbol.delisted ELSE TRUE END",
+            )
+            .bind(&symbol.name)
+            .bind(&symbol.code)
+            .bind(exchange_short_code)
+            .bind(&symbol.symbol_type)
+            .bind(&symbol.country)
+            .bind(&symbol.currency)
+            .bind(&symbol.isin)
+            .bind(symbol.exchange.as_ref())
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn backfill_seen_dates(&self, exchange_short_code: &str) -> sqlx::Result<()> {
+        sqlx::query(
+            "UPDATE ExchangeSymbol es
+             SET firstSeen = LEAST(es.firstSeen, p.first),
+                 lastSeen  = CASE WHEN es.delisted THEN GREATEST(es.lastSeen, p.last) ELSE es.lastSeen END
+             FROM (SELECT code, exchange, MIN(timestamp)::date AS first, MAX(timestamp)::date AS last
+                   FROM StockPrice
+                   WHERE exchange = $1
+                   GROUP BY code, exchange) p
+             WHERE es.code = p.code AND es.exchange = p.exchange",
+        )
+        .bind(exchange_short_code)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_exchange_symbol_codes(
+        &self,
+        exchange_short_code: &str,
+    ) -> sqlx::Result<Vec<Box<str>>> {
+        let codes = sqlx::query("SELECT code FROM ExchangeSymbol WHERE exchange = $1")
+            .bind(exchange_short_code)
+            .map(|row: PgRow| row.get(0))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(codes)
+    }
+
+    async fn get_outdated_symbol_prices(
+        &self,
+        exchange_short_code: &str,
+    ) -> sqlx::Result<Vec<OutdatedSymbolPrice>> {
+        sqlx::query_as::<_, OutdatedSymbolPrice>(
+            "SELECT ES.code, MAX(SP.timestamp) as last_updated
+             FROM ExchangeSymbol AS ES
+             LEFT JOIN StockPrice SP on ES.code = SP.code AND ES.exchange = SP.exchange
+             WHERE ES.exchange = $1
+             GROUP BY ES.code
+             HAVING MAX(SP.timestamp) IS NULL OR MAX(SP.timestamp)::date < CURRENT_DATE - 21",
+        )
+        .bind(exchange_short_code)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn add_stage(&self, exchange_short_code: &str, stage: &str) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO StageDone (exchange, stage)
+             VALUES ($1, $2)
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(exchange_short_code)
+        .bind(stage)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_stages(&self, exchange_short_code: &str) -> sqlx::Result<Vec<Box<str>>> {
+        let stages = sqlx::query("SELECT stage FROM StageDone WHERE exchange = $1")
+            .bind(exchange_short_code)
+            .map(|row: PgRow| row.get(0))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(stages)
+    }
+
+    async fn update_downloaded_symbol(
+        &self,
+        code: &str,
+        exchange: &str,
+        is_empty: bool,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO DownloadedSymbol (code, exchange, isEmpty)
+             VALUES ($1, $2, $3)
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(code)
+        .bind(exchange)
+        .bind(is_empty)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    fn mysql(self: Arc<Self>) -> Option<Arc<Db>> {
+        None
+    }
+}
+
+async fn push_symbol_change(
+    transaction: &mut sqlx::Transaction<'_, Postgres>,
+    code: &str,
+    exchange: &str,
+    change_type: ChangeType,
+    field: Option<&str>,
+    old_value: Option<Box<str>>,
+    new_value: Option<Box<str>>,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO SymbolChangeLog (code, exchange, changeType, field, oldValue, newValue)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(code)
+    .bind(exchange)
+    .bind(change_type.as_str())
+    .bind(field)
+    .bind(old_value)
+    .bind(new_value)
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+async fn push_quarantined(
+    transaction: &mut sqlx::Transaction<'_, Postgres>,
+    code: &str,
+    exchange: &str,
+    row: &PriceRow,
+    rule: Rule,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO QuarantinedPrice (code, exchange, source, timestamp, gmtoffset, open, high, low, close, adjusted_close, volume, rule)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+    )
+    .bind(code)
+    .bind(exchange)
+    .bind(PriceTable::Intraday.as_str())
+    .bind(row.timestamp)
+    .bind(row.gmtoffset)
+    .bind(row.open)
+    .bind(row.high)
+    .bind(row.low)
+    .bind(row.close)
+    .bind(row.adjusted_close)
+    .bind(row.volume)
+    .bind(rule.as_str())
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/**
+ * These need `DATABASE_URL` to point at a database set up with `schema.postgres.sql`, so they are
+ * ignored by default. Run them with `cargo test -- --ignored`. They only touch the `TEST` exchange.
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, NaiveDateTime, TimeDelta, TimeZone, Utc};
+
+    const EXCHANGE: &str = "TEST";
+
+    async fn database() -> PgDb {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL isn't set");
+        let db = PgDb::connect(&url, &PoolSettings::default()).await.unwrap();
+        for table in [
+            "QuarantinedPrice",
+            "StockPrice",
+            "DownloadedSymbol",
+            "SymbolChangeLog",
+            "ExchangeSymbol",
+        ] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE exchange = $1"))
+                .bind(EXCHANGE)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query("INSERT INTO Exchange (code) VALUES ($1) ON CONFLICT DO NOTHING")
+            .bind(EXCHANGE)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        db
+    }
+
+    fn symbol(code: &str, real_exchange: &str) -> ExchangeSymbol {
+        ExchangeSymbol {
+            code: code.into(),
+            name: format!("{code} Inc").into(),
+            country: "USA".into(),
+            exchange: real_exchange.into(),
+            currency: "USD".into(),
+            symbol_type: "Common Stock".into(),
+            isin: None,
+        }
+    }
+
+    fn intraday(datetime: DateTime<Utc>, close: f64) -> Intraday {
+        Intraday {
+            timestamp: datetime.timestamp(),
+            gmt_offset: 0,
+            datetime,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: Some(100),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn intraday_is_stored_once_in_utc() {
+        let db = database().await;
+        db.push_exchange_symbols(EXCHANGE, vec![symbol("AAA", "NYSE")])
+            .await
+            .unwrap();
+
+        let at = Utc.with_ymd_and_hms(2024, 3, 15, 14, 30, 0).unwrap();
+        let bars = [
+            intraday(at, 10.0),
+            intraday(at - TimeDelta::minutes(5), 9.0),
+        ];
+        db.push_intraday("AAA", EXCHANGE, &bars).await.unwrap();
+        db.push_intraday("AAA", EXCHANGE, &bars).await.unwrap();
+
+        let stored: Vec<NaiveDateTime> = sqlx::query_scalar(
+            "SELECT timestamp FROM StockPrice WHERE exchange = $1 ORDER BY timestamp DESC",
+        )
+        .bind(EXCHANGE)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            stored,
+            [at.naive_utc(), (at - TimeDelta::minutes(5)).naive_utc()]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn real_exchange_is_diffed() {
+        let db = database().await;
+        db.push_exchange_symbols(EXCHANGE, vec![symbol("AAA", "NYSE")])
+            .await
+            .unwrap();
+
+        let diff = db
+            .push_exchange_symbols(EXCHANGE, vec![symbol("AAA", "NYSE")])
+            .await
+            .unwrap();
+        assert_eq!((diff.added, diff.removed, diff.changed), (0, 0, 0));
+
+        let diff = db
+            .push_exchange_symbols(EXCHANGE, vec![symbol("AAA", "NASDAQ")])
+            .await
+            .unwrap();
+        assert_eq!((diff.added, diff.removed, diff.changed), (0, 0, 1));
+        let field: Option<String> = sqlx::query_scalar(
+            "SELECT field FROM SymbolChangeLog WHERE exchange = $1 AND changeType = 'CHANGED'",
+        )
+        .bind(EXCHANGE)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(field.as_deref(), Some("realExchange"));
+    }
+}
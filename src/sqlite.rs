@@ -0,0 +1,570 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteRow};
+use sqlx::{Executor, Pool, Row, Sqlite};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::db::{
+    diff_listing, ChangeType, Db, OutdatedSymbolPrice, StoredExchangeSymbol, SymbolListDiff,
+};
+use crate::models::{ExchangeSymbol, Intraday};
+use crate::storage::{PoolSettings, Storage};
+use crate::validate::{gate, PriceRow, PriceTable, Rule, Validator};
+
+const SCHEMA: &str = include_str!("../schema.sqlite.sql");
+
+/**
+ * A single SQLite file, or `sqlite::memory:`. The file and `schema.sqlite.sql` are created on
+ * connect, so there's nothing to set up.
+ */
+pub struct SqliteDb {
+    pool: Pool<Sqlite>,
+    gate: Option<Validator>,
+}
+
+impl SqliteDb {
+    pub async fn connect(url: &str, settings: &PoolSettings) -> sqlx::Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .foreign_keys(true);
+        let mut pool_options = settings.pool_options();
+        // Every connection to an in-memory database gets a database of its own
+        if url.contains(":memory:") {
+            pool_options = pool_options.max_connections(1).min_connections(1);
+        }
+        let pool = pool_options.connect_with(options).await?;
+        pool.execute(SCHEMA).await?;
+        Ok(Self {
+            pool,
+            gate: settings.gate.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteDb {
+    async fn push_intraday(
+        &self,
+        code: &str,
+        exchange: &str,
+        intraday_prices: &[Intraday],
+    ) -> Result<()> {
+        let (intraday_prices, rejected) = gate(self.gate.as_ref(), code, exchange, intraday_prices);
+        let mut transaction = self.pool.begin().await?;
+
+        for (row, rule) in &rejected {
+            push_quarantined(&mut transaction, code, exchange, row, *rule).await?;
+        }
+
+        for intraday in intraday_prices {
+            sqlx::query(
+                "INSERT OR IGNORE INTO StockPrice (code, exchange, timestamp, gmtoffset, open, high, low, close, volume)
+                 VALUES (?, ?, datetime(?, 'unixepoch'), ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(code)
+            .bind(exchange)
+            .bind(intraday.timestamp)
+            .bind(intraday.gmt_offset)
+            .bind(intraday.open)
+            .bind(intraday.high)
+            .bind(intraday.low)
+            .bind(intraday.close)
+            .bind(intraday.volume)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn push_exchange_symbols(
+        &self,
+        exchange_short_code: &str,
+        symbols: Vec<ExchangeSymbol>,
+    ) -> Result<SymbolListDiff> {
+        let mut transaction = self.pool.begin().await?;
+
+        let stored = sqlx::query_as::<_, StoredExchangeSymbol>(
+            "SELECT code, name, type, country, currency, isin, realExchange, delisted
+             FROM ExchangeSymbol
+             WHERE exchange = ?",
+        )
+        .bind(exchange_short_code)
+        .fetch_all(&mut *transaction)
+        .await?;
+        let listing = diff_listing(stored, symbols);
+
+        for (code, (change_type, field, old_value, new_value)) in listing.changes {
+            push_symbol_change(
+                &mut transaction,
+                &code,
+                exchange_short_code,
+                change_type,
+                field,
+                old_value,
+                new_value,
+            )
+            .await?;
+        }
+
+        for symbol in &listing.symbols {
+            sqlx::query(
+                "INSERT INTO ExchangeSymbol (name, code, exchange, type, country, currency, isin, realExchange, delisted, firstSeen, lastSeen)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, FALSE, date('now'), date('now'))
+                 ON CONFLICT (code, exchange) DO UPDATE SET
+                    name = excluded.name, type = excluded.type, country = excluded.country,
+                    currency = excluded.currency, isin = excluded.isin, realExchange = excluded.realExchange,
+                    delisted = FALSE, lastSeen = date('now')",
+            )
+            .bind(&symbol.name)
+            .bind(&symbol.code)
+            .bind(exchange_short_code)
+            .bind(&symbol.symbol_type)
+            .bind(&symbol.country)
+            .bind(&symbol.currency)
+            .bind(&symbol.isin)
+            .bind(symbol.exchange.as_ref())
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for code in &listing.removed {
+            sqlx::query(
+                "UPDATE ExchangeSymbol SET delisted = TRUE WHERE code = ? AND exchange = ?",
+            )
+            .bind(code)
+            .bind(exchange_short_code)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(listing.diff)
+    }
+
+    async fn push_delisted_exchange_symbols(
+        &self,
+        exchange_short_code: &str,
+        symbols: Vec<ExchangeSymbol>,
+    ) -> Result<()> {
+        let mut transaction = self.pool.begin().await?;
+
+        for symbol in symbols {
+            sqlx::query(
+                "INSERT INTO ExchangeSymbol (name, code, exchange, type, country, currency, isin, realExchange, delisted)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, TRUE)
+                 ON CONFLICT (code, exchange) DO UPDATE SET
+                    delisted = CASE WHEN lastSeen = date('now') THEN delisted ELSE TRUE END",
+            )
+            .bind(&symbol.name)
+            .bind(&symbol.code)
+            .bind(exchange_short_code)
+            .bind(&symbol.symbol_type)
+            .bind(&symbol.country)
+            .bind(&symbol.currency)
+            .bind(&symbol.isin)
+            .bind(symbol.exchange.as_ref())
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn backfill_seen_dates(&self, exchange_short_code: &str) -> sqlx::Result<()> {
+        sqlx::query(
+            "UPDATE ExchangeSymbol AS es
+             SET firstSeen = MIN(COALESCE(es.firstSeen, p.first), p.first),
+                 lastSeen  = CASE WHEN es.delisted THEN MAX(COALESCE(es.lastSeen, p.last), p.last) ELSE es.lastSeen END
+             FROM (SELECT code, exchange, date(MIN(timestamp)) AS first, date(MAX(timestamp)) AS last
+                   FROM StockPrice
+                   WHERE exchange = ?
+                   GROUP BY code, exchange) AS p
+             WHERE es.code = p.code AND es.exchange = p.exchange",
+        )
+        .bind(exchange_short_code)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_exchange_symbol_codes(
+        &self,
+        exchange_short_code: &str,
+    ) -> sqlx::Result<Vec<Box<str>>> {
+        let codes = sqlx::query("SELECT code FROM ExchangeSymbol WHERE exchange = ?")
+            .bind(exchange_short_code)
+            .map(|row: SqliteRow| row.get(0))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(codes)
+    }
+
+    async fn get_outdated_symbol_prices(
+        &self,
+        exchange_short_code: &str,
+    ) -> sqlx::Result<Vec<OutdatedSymbolPrice>> {
+        sqlx::query_as::<_, OutdatedSymbolPrice>(
+            "SELECT ES.code, MAX(SP.timestamp) as last_updated
+             FROM ExchangeSymbol AS ES
+             LEFT JOIN StockPrice SP on ES.code = SP.code AND ES.exchange = SP.exchange
+             WHERE ES.exchange = ?
+             GROUP BY ES.code
+             HAVING MAX(SP.timestamp) IS NULL OR date(MAX(SP.timestamp)) < date('now', '-21 days')",
+        )
+        .bind(exchange_short_code)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn add_stage(&self, exchange_short_code: &str, stage: &str) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO StageDone (exchange, stage)
+             VALUES (?, ?)
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(exchange_short_code)
+        .bind(stage)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_stages(&self, exchange_short_code: &str) -> sqlx::Result<Vec<Box<str>>> {
+        let stages = sqlx::query("SELECT stage FROM StageDone WHERE exchange = ?")
+            .bind(exchange_short_code)
+            .map(|row: SqliteRow| row.get(0))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(stages)
+    }
+
+    async fn update_downloaded_symbol(
+        &self,
+        code: &str,
+        exchange: &str,
+        is_empty: bool,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO DownloadedSymbol (code, exchange, isEmpty)
+             VALUES (?, ?, ?)
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(code)
+        .bind(exchange)
+        .bind(is_empty)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    fn mysql(self: Arc<Self>) -> Option<Arc<Db>> {
+        None
+    }
+}
+
+async fn push_symbol_change(
+    transaction: &mut sqlx::Transaction<'_, Sqlite>,
+    code: &str,
+    exchange: &str,
+    change_type: ChangeType,
+    field: Option<&str>,
+    old_value: Option<Box<str>>,
+    new_value: Option<Box<str>>,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO SymbolChangeLog (code, exchange, changeType, field, oldValue, newValue)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(code)
+    .bind(exchange)
+    .bind(change_type.as_str())
+    .bind(field)
+    .bind(old_value)
+    .bind(new_value)
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+async fn push_quarantined(
+    transaction: &mut sqlx::Transaction<'_, Sqlite>,
+    code: &str,
+    exchange: &str,
+    row: &PriceRow,
+    rule: Rule,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO QuarantinedPrice (code, exchange, source, timestamp, gmtoffset, open, high, low, close, adjusted_close, volume, rule)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(code)
+    .bind(exchange)
+    .bind(PriceTable::Intraday.as_str())
+    .bind(row.timestamp)
+    .bind(row.gmtoffset)
+    .bind(row.open)
+    .bind(row.high)
+    .bind(row.low)
+    .bind(row.close)
+    .bind(row.adjusted_close)
+    .bind(row.volume)
+    .bind(rule.as_str())
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    async fn memory() -> SqliteDb {
+        SqliteDb::connect("sqlite::memory:", &PoolSettings::default())
+            .await
+            .unwrap()
+    }
+
+    fn symbol(code: &str) -> ExchangeSymbol {
+        ExchangeSymbol {
+            code: code.into(),
+            name: format!("{code} Inc").into(),
+            country: "USA".into(),
+            exchange: "NYSE".into(),
+            currency: "USD".into(),
+            symbol_type: "Common Stock".into(),
+            isin: None,
+        }
+    }
+
+    async fn change_count(db: &SqliteDb) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM SymbolChangeLog")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap()
+    }
+
+    async fn is_delisted(db: &SqliteDb, code: &str) -> bool {
+        sqlx::query_scalar("SELECT delisted FROM ExchangeSymbol WHERE code = ? AND exchange = 'US'")
+            .bind(code)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap()
+    }
+
+    fn intraday(datetime: chrono::DateTime<chrono::Utc>, close: f64) -> Intraday {
+        Intraday {
+            timestamp: datetime.timestamp(),
+            gmt_offset: 0,
+            datetime,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: Some(100),
+        }
+    }
+
+    async fn outdated_price_codes(db: &SqliteDb) -> Vec<Box<str>> {
+        let mut codes = db
+            .get_outdated_symbol_prices("US")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| x.code)
+            .collect::<Vec<_>>();
+        codes.sort();
+        codes
+    }
+
+    #[tokio::test]
+    async fn listing_diff_is_logged() {
+        let db = memory().await;
+        db.push_exchange_symbols("US", vec![symbol("AAA"), symbol("BBB")])
+            .await
+            .unwrap();
+
+        let mut renamed = symbol("AAA");
+        renamed.name = "AAA Holdings".into();
+        let diff = db
+            .push_exchange_symbols("US", vec![renamed, symbol("CCC"), symbol("CCC")])
+            .await
+            .unwrap();
+        assert_eq!((diff.added, diff.removed, diff.changed), (1, 1, 1));
+        assert!(is_delisted(&db, "BBB").await);
+        assert!(!is_delisted(&db, "CCC").await);
+        assert_eq!(change_count(&db).await, 5);
+
+        // An empty listing is taken as a hiccup and delists nothing
+        let diff = db.push_exchange_symbols("US", Vec::new()).await.unwrap();
+        assert_eq!((diff.added, diff.removed, diff.changed), (0, 0, 0));
+        assert!(!is_delisted(&db, "AAA").await);
+    }
+
+    #[tokio::test]
+    async fn intraday_is_stored_once_and_needs_a_symbol() {
+        let db = memory().await;
+        db.push_exchange_symbols("US", vec![symbol("AAA")])
+            .await
+            .unwrap();
+
+        let now = chrono::Utc::now();
+        let bars = [
+            intraday(now, 10.0),
+            intraday(now - TimeDelta::minutes(5), 9.0),
+        ];
+        db.push_intraday("AAA", "US", &bars).await.unwrap();
+        db.push_intraday("AAA", "US", &bars).await.unwrap();
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM StockPrice")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, 2);
+
+        assert!(db.push_intraday("ZZZ", "US", &bars).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn seen_dates_cover_the_prices() {
+        let db = memory().await;
+        db.push_exchange_symbols("US", vec![symbol("AAA")])
+            .await
+            .unwrap();
+        let month_ago = chrono::Utc::now() - TimeDelta::days(30);
+        db.push_intraday("AAA", "US", &[intraday(month_ago, 10.0)])
+            .await
+            .unwrap();
+
+        db.backfill_seen_dates("US").await.unwrap();
+        let first_seen: chrono::NaiveDate =
+            sqlx::query_scalar("SELECT firstSeen FROM ExchangeSymbol WHERE code = 'AAA'")
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(first_seen, month_ago.date_naive());
+    }
+
+    #[tokio::test]
+    async fn gated_bars_are_quarantined() {
+        let settings = PoolSettings {
+            gate: Some(Validator::default()),
+            ..Default::default()
+        };
+        let db = SqliteDb::connect("sqlite::memory:", &settings)
+            .await
+            .unwrap();
+        db.push_exchange_symbols("US", vec![symbol("AAA")])
+            .await
+            .unwrap();
+
+        let now = chrono::Utc::now();
+        let mut bad = intraday(now, 10.0);
+        bad.low = 12.0;
+        db.push_intraday(
+            "AAA",
+            "US",
+            &[intraday(now - TimeDelta::minutes(5), 10.0), bad],
+        )
+        .await
+        .unwrap();
+        let count = |table| {
+            let pool = &db.pool;
+            async move {
+                sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {table}"))
+                    .fetch_one(pool)
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(count("StockPrice").await, 1);
+        assert_eq!(count("QuarantinedPrice").await, 1);
+        let rule: String = sqlx::query_scalar("SELECT rule FROM QuarantinedPrice")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(rule, Rule::HighBelowLow.as_str());
+    }
+
+    #[tokio::test]
+    async fn symbols_without_recent_prices_are_outdated() {
+        let db = memory().await;
+        db.push_exchange_symbols("US", vec![symbol("AAA"), symbol("BBB"), symbol("CCC")])
+            .await
+            .unwrap();
+        let now = chrono::Utc::now();
+        db.push_intraday("AAA", "US", &[intraday(now, 10.0)])
+            .await
+            .unwrap();
+        db.push_intraday("BBB", "US", &[intraday(now - TimeDelta::days(30), 10.0)])
+            .await
+            .unwrap();
+        assert_eq!(
+            outdated_price_codes(&db).await,
+            ["BBB".into(), "CCC".into()]
+        );
+
+        assert!(db
+            .get_outdated_symbol_prices("LSE")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn stages_are_recorded_once() {
+        let db = memory().await;
+        db.add_stage("US", "INTRADAY").await.unwrap();
+        db.add_stage("US", "INTRADAY").await.unwrap();
+        db.add_stage("US", "AGGREGATE").await.unwrap();
+        db.add_stage("LSE", "EOD").await.unwrap();
+
+        let mut stages = db.get_stages("US").await.unwrap();
+        stages.sort();
+        assert_eq!(stages, ["AGGREGATE".into(), "INTRADAY".into()]);
+        assert!(db.add_stage("US", "NOT_A_STAGE").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn downloaded_symbols_need_a_symbol() {
+        let db = memory().await;
+        db.push_exchange_symbols("US", vec![symbol("AAA")])
+            .await
+            .unwrap();
+        db.update_downloaded_symbol("AAA", "US", false)
+            .await
+            .unwrap();
+        db.update_downloaded_symbol("AAA", "US", true)
+            .await
+            .unwrap();
+        assert!(db
+            .update_downloaded_symbol("ZZZ", "US", false)
+            .await
+            .is_err());
+    }
+
+    /// `BBB` is a reused ticker, listed today and on the delisted list from its previous owner
+    #[tokio::test]
+    async fn resync_of_reused_ticker_logs_nothing() {
+        let db = memory().await;
+        for run in 0..2 {
+            let diff = db
+                .push_exchange_symbols("US", vec![symbol("AAA"), symbol("BBB")])
+                .await
+                .unwrap();
+            db.push_delisted_exchange_symbols("US", vec![symbol("BBB"), symbol("CCC")])
+                .await
+                .unwrap();
+
+            let expected_added = if run == 0 { 2 } else { 0 };
+            assert_eq!(diff.added, expected_added, "run {run}");
+            assert_eq!((diff.removed, diff.changed), (0, 0), "run {run}");
+            assert_eq!(change_count(&db).await, 2, "run {run}");
+            assert!(!is_delisted(&db, "BBB").await);
+            assert!(is_delisted(&db, "CCC").await);
+        }
+    }
+}
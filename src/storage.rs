@@ -0,0 +1,182 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use sqlx::mysql::MySqlSslMode;
+use sqlx::pool::PoolOptions;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db::{Db, OutdatedSymbolPrice, SymbolListDiff};
+use crate::models::{ExchangeSymbol, Intraday};
+use crate::postgres::PgDb;
+use crate::sqlite::SqliteDb;
+use crate::validate::Validator;
+
+/**
+ * What every database backend can do: prices, listings and bookkeeping. That is enough for
+ * `selective` and the intraday stage of `dump`, which is all Postgres and SQLite support.
+ * Every other stage and command only exists for MySQL, see `mysql`, and is refused up front
+ * on the other backends.
+ */
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Bars the gate from `PoolSettings` rejects go to `QuarantinedPrice` instead
+    async fn push_intraday(
+        &self,
+        code: &str,
+        exchange: &str,
+        intraday_prices: &[Intraday],
+    ) -> Result<()>;
+
+    /**
+     * Upserts the listing of an exchange and diffs it against what was stored before.
+     * Every addition, removal and changed field is written to `SymbolChangeLog`. Symbols
+     * missing from a non-empty listing are marked as delisted.
+     */
+    async fn push_exchange_symbols(
+        &self,
+        exchange_short_code: &str,
+        symbols: Vec<ExchangeSymbol>,
+    ) -> Result<SymbolListDiff>;
+
+    /**
+     * Delisted symbols are stored without `firstSeen`/`lastSeen` since we never saw them trade.
+     * Symbols seen in today's listing are reused tickers and stay listed, so run this after
+     * `push_exchange_symbols`.
+     */
+    async fn push_delisted_exchange_symbols(
+        &self,
+        exchange_short_code: &str,
+        symbols: Vec<ExchangeSymbol>,
+    ) -> Result<()>;
+
+    /**
+     * Widens `firstSeen` (and `lastSeen` for delisted symbols) to cover the prices we have,
+     * since a symbol obviously traded on the days it has prices for.
+     */
+    async fn backfill_seen_dates(&self, exchange_short_code: &str) -> sqlx::Result<()>;
+
+    async fn get_exchange_symbol_codes(
+        &self,
+        exchange_short_code: &str,
+    ) -> sqlx::Result<Vec<Box<str>>>;
+
+    async fn get_outdated_symbol_prices(
+        &self,
+        exchange_short_code: &str,
+    ) -> sqlx::Result<Vec<OutdatedSymbolPrice>>;
+
+    async fn add_stage(&self, exchange_short_code: &str, stage: &str) -> sqlx::Result<()>;
+
+    async fn get_stages(&self, exchange_short_code: &str) -> sqlx::Result<Vec<Box<str>>>;
+
+    async fn update_downloaded_symbol(
+        &self,
+        code: &str,
+        exchange: &str,
+        is_empty: bool,
+    ) -> sqlx::Result<()>;
+
+    /// The MySQL database behind this, if it is one
+    fn mysql(self: Arc<Self>) -> Option<Arc<Db>>;
+}
+
+/// How a backend is set up. Whatever is left as `None` in the pool settings is up to sqlx
+#[derive(Debug, Default, Clone)]
+pub struct PoolSettings {
+    pub max_connections: Option<u32>,
+    pub min_connections: u32,
+    pub acquire_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    /// MySQL only, the other backends refuse it. Postgres takes `sslmode` in the URL
+    pub ssl_mode: Option<MySqlSslMode>,
+    /// MySQL only, the other backends refuse it. Postgres takes `sslrootcert` in the URL
+    pub ssl_ca: Option<PathBuf>,
+    /// Checks every intraday bar before it's stored, quarantining the ones that break a rule
+    pub gate: Option<Validator>,
+}
+
+impl PoolSettings {
+    /// Enough connections for `workers` pushing at once, plus a couple for bookkeeping
+    pub fn for_workers(workers: usize) -> Self {
+        Self {
+            max_connections: Some(workers as u32 + 2),
+            ..Default::default()
+        }
+    }
+
+    pub fn pool_options<DB: sqlx::Database>(&self) -> PoolOptions<DB> {
+        let mut options = PoolOptions::new().min_connections(self.min_connections);
+        if let Some(max_connections) = self.max_connections {
+            options = options.max_connections(max_connections);
+        }
+        if let Some(acquire_timeout) = self.acquire_timeout {
+            options = options.acquire_timeout(acquire_timeout);
+        }
+        if self.idle_timeout.is_some() {
+            options = options.idle_timeout(self.idle_timeout);
+        }
+        options
+    }
+}
+
+/// Picks the backend from the scheme of `url`, e.g. `mysql://`, `postgres://` or `sqlite://`
+pub async fn connect(url: &str, settings: &PoolSettings) -> Result<Arc<dyn Storage>> {
+    let scheme = url.split_once("://").map(|(scheme, _)| scheme);
+    match scheme {
+        Some("mysql" | "mariadb") => Ok(Arc::new(Db::connect(url, settings).await?)),
+        Some("postgres" | "postgresql") => {
+            reject_mysql_ssl(
+                settings,
+                "Postgres takes sslmode and sslrootcert in the URL",
+            )?;
+            Ok(Arc::new(PgDb::connect(url, settings).await?))
+        }
+        Some("sqlite") => {
+            reject_mysql_ssl(settings, "SQLite has no connection to encrypt")?;
+            Ok(Arc::new(SqliteDb::connect(url, settings).await?))
+        }
+        None if url.starts_with("sqlite:") => {
+            reject_mysql_ssl(settings, "SQLite has no connection to encrypt")?;
+            Ok(Arc::new(SqliteDb::connect(url, settings).await?))
+        }
+        _ => bail!(
+            "Unsupported database URL scheme '{}'. Expected mysql://, postgres:// or sqlite://",
+            scheme.unwrap_or_default()
+        ),
+    }
+}
+
+/// `ssl_mode` and `ssl_ca` would otherwise be silently ignored by the other backends
+fn reject_mysql_ssl(settings: &PoolSettings, hint: &str) -> Result<()> {
+    if settings.ssl_mode.is_some() || settings.ssl_ca.is_some() {
+        bail!("--ssl-mode and --ssl-ca only apply to MySQL. {hint}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mysql_ssl_options_are_rejected_elsewhere() {
+        let settings = PoolSettings {
+            ssl_mode: Some(MySqlSslMode::Required),
+            ..Default::default()
+        };
+        assert!(connect("sqlite::memory:", &settings).await.is_err());
+        assert!(connect("postgres://localhost/none", &settings)
+            .await
+            .is_err_and(|e| e.to_string().contains("--ssl-mode")));
+
+        let settings = PoolSettings {
+            ssl_ca: Some("ca.pem".into()),
+            ..Default::default()
+        };
+        assert!(connect("sqlite://:memory:", &settings).await.is_err());
+        assert!(connect("sqlite::memory:", &PoolSettings::default())
+            .await
+            .is_ok());
+    }
+}
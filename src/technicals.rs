@@ -0,0 +1,269 @@
+use anyhow::{anyhow, bail, Result};
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Used when no indicators are asked for
+pub const DEFAULT_INDICATORS: &[Indicator] = &[
+    Indicator::Sma(20),
+    Indicator::Sma(50),
+    Indicator::Sma(200),
+    Indicator::Ema(20),
+    Indicator::Rsi(14),
+    Indicator::Macd(12, 26, 9),
+];
+
+/// A technical indicator computed from daily closes.
+/// Parsed from e.g. `sma:20`, `ema:50`, `rsi:14` or `macd:12:26:9`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indicator {
+    Sma(usize),
+    Ema(usize),
+    Rsi(usize),
+    /// Fast, slow and signal periods
+    Macd(usize, usize, usize),
+}
+
+impl Indicator {
+    /**
+     * Computes every series this indicator produces, named the way they're stored in
+     * `Technicals.indicator`. Each series is aligned with `closes` and is `None` during warm up.
+     */
+    pub fn compute(&self, closes: &[f64]) -> Vec<(String, Vec<Option<f64>>)> {
+        let closes = closes.iter().copied().map(Some).collect::<Vec<_>>();
+        match *self {
+            Self::Sma(n) => vec![(format!("SMA_{n}"), sma(&closes, n))],
+            Self::Ema(n) => vec![(format!("EMA_{n}"), ema(&closes, n))],
+            Self::Rsi(n) => vec![(format!("RSI_{n}"), rsi(&closes, n))],
+            Self::Macd(fast, slow, signal) => {
+                let (fast_ema, slow_ema) = (ema(&closes, fast), ema(&closes, slow));
+                let line = fast_ema
+                    .iter()
+                    .zip(&slow_ema)
+                    .map(|(f, s)| Some(f.as_ref()? - s.as_ref()?))
+                    .collect::<Vec<_>>();
+                let signal_line = ema(&line, signal);
+                let histogram = line
+                    .iter()
+                    .zip(&signal_line)
+                    .map(|(l, s)| Some(l.as_ref()? - s.as_ref()?))
+                    .collect();
+                let suffix = format!("{fast}_{slow}_{signal}");
+                vec![
+                    (format!("MACD_{suffix}"), line),
+                    (format!("MACD_SIGNAL_{suffix}"), signal_line),
+                    (format!("MACD_HIST_{suffix}"), histogram),
+                ]
+            }
+        }
+    }
+}
+
+impl FromStr for Indicator {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default().to_lowercase();
+        let periods = parts
+            .map(|p| match p.parse::<usize>() {
+                Ok(0) | Err(_) => Err(anyhow!("Invalid period '{p}' in indicator '{s}'")),
+                Ok(n) => Ok(n),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        match (name.as_str(), periods.as_slice()) {
+            ("sma", &[n]) => Ok(Self::Sma(n)),
+            ("ema", &[n]) => Ok(Self::Ema(n)),
+            ("rsi", &[n]) => Ok(Self::Rsi(n)),
+            ("macd", &[fast, slow, signal]) if fast < slow => Ok(Self::Macd(fast, slow, signal)),
+            _ => bail!(
+                "Unknown indicator '{s}'. Expected sma:N, ema:N, rsi:N or macd:FAST:SLOW:SIGNAL"
+            ),
+        }
+    }
+}
+
+impl Display for Indicator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sma(n) => write!(f, "sma:{n}"),
+            Self::Ema(n) => write!(f, "ema:{n}"),
+            Self::Rsi(n) => write!(f, "rsi:{n}"),
+            Self::Macd(fast, slow, signal) => write!(f, "macd:{fast}:{slow}:{signal}"),
+        }
+    }
+}
+
+fn sma(values: &[Option<f64>], n: usize) -> Vec<Option<f64>> {
+    let mut ret = vec![None; values.len()];
+    for (i, out) in ret.iter_mut().enumerate().skip(n.saturating_sub(1)) {
+        let window = &values[i + 1 - n..=i];
+        if window.iter().all(Option::is_some) {
+            *out = Some(window.iter().flatten().sum::<f64>() / n as f64);
+        }
+    }
+    ret
+}
+
+/**
+ * Seeded with the simple average of the first `n` values, so that leading `None`s
+ * (e.g. the warm up of another indicator) are skipped.
+ */
+fn ema(values: &[Option<f64>], n: usize) -> Vec<Option<f64>> {
+    let mut ret = vec![None; values.len()];
+    let alpha = 2.0 / (n as f64 + 1.0);
+    let Some(start) = values.iter().position(Option::is_some) else {
+        return ret;
+    };
+    if values.len() < start + n {
+        return ret;
+    }
+
+    let seed_at = start + n - 1;
+    let mut prev = sma(&values[start..=seed_at], n)[n - 1];
+    ret[seed_at] = prev;
+    for (i, value) in values.iter().enumerate().skip(seed_at + 1) {
+        prev = match (prev, value) {
+            (Some(prev), Some(value)) => Some(alpha * value + (1.0 - alpha) * prev),
+            _ => None,
+        };
+        ret[i] = prev;
+    }
+    ret
+}
+
+/// Wilder's RSI
+fn rsi(values: &[Option<f64>], n: usize) -> Vec<Option<f64>> {
+    let mut ret = vec![None; values.len()];
+    if values.len() <= n || values.iter().any(Option::is_none) {
+        return ret;
+    }
+    let values = values.iter().flatten().copied().collect::<Vec<_>>();
+    let changes = values.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>();
+
+    let mut avg_gain = changes[..n].iter().map(|c| c.max(0.0)).sum::<f64>() / n as f64;
+    let mut avg_loss = changes[..n].iter().map(|c| (-c).max(0.0)).sum::<f64>() / n as f64;
+    let to_rsi = |gain: f64, loss: f64| {
+        if loss == 0.0 {
+            100.0
+        } else {
+            100.0 - 100.0 / (1.0 + gain / loss)
+        }
+    };
+
+    ret[n] = Some(to_rsi(avg_gain, avg_loss));
+    for (i, change) in changes.iter().enumerate().skip(n) {
+        avg_gain = (avg_gain * (n - 1) as f64 + change.max(0.0)) / n as f64;
+        avg_loss = (avg_loss * (n - 1) as f64 + (-change).max(0.0)) / n as f64;
+        ret[i + 1] = Some(to_rsi(avg_gain, avg_loss));
+    }
+    ret
+}
+
+/// Where the daily closes come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceSource {
+    /// `StockPriceEOD`
+    Eod,
+    /// The last bar of each day in `StockPrice`
+    Intraday,
+}
+
+impl FromStr for PriceSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "eod" => Ok(Self::Eod),
+            "intraday" => Ok(Self::Intraday),
+            _ => bail!("Unknown price source '{s}'. Expected eod or intraday"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_series(actual: &[Option<f64>], expected: &[Option<f64>]) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            match (a, e) {
+                (Some(a), Some(e)) => assert!((a - e).abs() < 0.01, "{i}: {a} != {e}"),
+                _ => assert_eq!(a, e, "at {i}"),
+            }
+        }
+    }
+
+    fn some(values: &[f64]) -> Vec<Option<f64>> {
+        values.iter().copied().map(Some).collect()
+    }
+
+    #[test]
+    fn sma_averages_full_windows() {
+        assert_series(
+            &sma(&some(&[1.0, 2.0, 3.0, 4.0, 5.0]), 3),
+            &[None, None, Some(2.0), Some(3.0), Some(4.0)],
+        );
+        assert_series(
+            &sma(&[Some(1.0), None, Some(3.0), Some(4.0)], 2),
+            &[None, None, None, Some(3.5)],
+        );
+    }
+
+    #[test]
+    fn ema_is_seeded_with_sma() {
+        assert_series(
+            &ema(&some(&[1.0, 2.0, 3.0, 4.0, 5.0]), 3),
+            &[None, None, Some(2.0), Some(3.0), Some(4.0)],
+        );
+        assert_series(
+            &ema(&[None, Some(2.0), Some(4.0), Some(8.0)], 2),
+            &[None, None, Some(3.0), Some(6.333)],
+        );
+    }
+
+    /// The worked example from StockCharts' RSI article. It rounds the averages it starts from,
+    /// which gives 70.53 rather than 70.46 for the first value
+    #[test]
+    fn rsi_matches_wilder() {
+        let closes = some(&[
+            44.34, 44.09, 44.15, 43.61, 44.33, 44.83, 45.10, 45.42, 45.84, 46.08, 45.89, 46.03,
+            45.61, 46.28, 46.28, 46.00, 46.03, 46.41, 46.22,
+        ]);
+        let result = rsi(&closes, 14);
+        assert!(result[..14].iter().all(Option::is_none));
+        assert_series(&result[14..], &some(&[70.46, 66.25, 66.48, 69.35, 66.29]));
+        assert_eq!(rsi(&some(&[1.0, 2.0, 3.0]), 2)[2], Some(100.0));
+    }
+
+    /// On a straight line both EMAs lag by `(n - 1) / 2` steps, so the line is constant
+    #[test]
+    fn macd_of_a_straight_line() {
+        let closes = (1..=10).map(f64::from).collect::<Vec<_>>();
+        let series = Indicator::Macd(2, 4, 3).compute(&closes);
+        let names = series.iter().map(|(x, _)| x.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["MACD_2_4_3", "MACD_SIGNAL_2_4_3", "MACD_HIST_2_4_3"]
+        );
+
+        let (line, signal, histogram) = (&series[0].1, &series[1].1, &series[2].1);
+        assert!(line[..3].iter().all(Option::is_none));
+        assert_series(&line[3..], &[Some(1.0); 7]);
+        assert!(signal[..5].iter().all(Option::is_none));
+        assert_series(&signal[5..], &[Some(1.0); 5]);
+        assert_series(&histogram[5..], &[Some(0.0); 5]);
+    }
+
+    #[test]
+    fn parses_indicators() {
+        assert_eq!("SMA:20".parse::<Indicator>().unwrap(), Indicator::Sma(20));
+        assert_eq!(
+            "macd:12:26:9".parse::<Indicator>().unwrap(),
+            Indicator::Macd(12, 26, 9)
+        );
+        assert!("macd:26:12:9".parse::<Indicator>().is_err());
+        assert!("sma:0".parse::<Indicator>().is_err());
+    }
+}
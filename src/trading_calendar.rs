@@ -0,0 +1,572 @@
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc, Weekday};
+use chrono_tz::{OffsetComponents, Tz};
+use std::collections::BTreeMap;
+
+use crate::models::{ExchangeDetails, ExchangeHoliday};
+
+const WEEKDAYS: &[Weekday] = &[
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+];
+const ALL_DAYS: &[Weekday] = &[
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+/// The first year the US fallback knows holidays for, matching how far back intraday goes
+const FALLBACK_FIRST_YEAR: i32 = 2020;
+/// Days NYSE closed outside its regular holidays, since `FALLBACK_FIRST_YEAR`
+const NYSE_SPECIAL_CLOSURES: &[(i32, u32, u32, &str)] =
+    &[(2025, 1, 9, "National Day of Mourning for Jimmy Carter")];
+
+/**
+ * When an exchange trades: the days of the week, holidays and session hours.
+ * Daylight saving time comes from the time zone database, the UTC hours of an exchange without a
+ * known `timezone` are taken to hold all year. Dates are always local.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradingCalendar {
+    pub exchange: Box<str>,
+    /// E.g. `America/New_York`
+    pub timezone: Option<Box<str>>,
+    pub open: NaiveTime,
+    pub close: NaiveTime,
+    /// Outside daylight saving time, see `utc_hours` for a given date
+    pub open_utc: NaiveTime,
+    /// Outside daylight saving time, see `utc_hours` for a given date
+    pub close_utc: NaiveTime,
+    pub working_days: Vec<Weekday>,
+    pub holidays: BTreeMap<NaiveDate, Box<str>>,
+}
+
+impl TradingCalendar {
+    /**
+     * Whatever EODHD leaves out is taken from `fallback`. EODHD gives the UTC hours as of `fetched_on`,
+     * which are moved out of daylight saving time if it was in effect then.
+     */
+    pub fn from_details(details: ExchangeDetails, fetched_on: NaiveDate) -> Self {
+        let fallback = Self::fallback(&details.code);
+        let hours = details.trading_hours;
+        let working_days = hours
+            .working_days
+            .as_deref()
+            .map(parse_weekdays)
+            .filter(|x| !x.is_empty())
+            .unwrap_or(fallback.working_days);
+        let timezone = details.timezone.or(fallback.timezone);
+        let shift = daylight_saving_shift(timezone.as_deref(), fetched_on);
+        let (open_utc, close_utc) = match (hours.open_utc, hours.close_utc) {
+            (Some(open_utc), Some(close_utc)) => (
+                open_utc.overflowing_add_signed(shift).0,
+                close_utc.overflowing_add_signed(shift).0,
+            ),
+            _ => (fallback.open_utc, fallback.close_utc),
+        };
+
+        Self {
+            exchange: details.code,
+            timezone,
+            open: hours.open.unwrap_or(fallback.open),
+            close: hours.close.unwrap_or(fallback.close),
+            open_utc,
+            close_utc,
+            working_days,
+            holidays: details
+                .holidays
+                .into_iter()
+                .filter(ExchangeHoliday::is_closure)
+                .map(|x| (x.date, x.holiday))
+                .collect(),
+        }
+    }
+
+    /**
+     * What to go by when EODHD hasn't been asked. NYSE hours and holidays for `US`, around the clock
+     * for crypto, Sunday 22:00 to Friday 22:00 UTC for forex and every weekday all day for anything else.
+     */
+    pub fn fallback(exchange: &str) -> Self {
+        let midnight = NaiveTime::MIN;
+        let last_minute = NaiveTime::from_hms_opt(23, 59, 59).unwrap();
+        match exchange {
+            "US" => {
+                let this_year = Utc::now().year();
+                Self {
+                    exchange: exchange.into(),
+                    timezone: Some("America/New_York".into()),
+                    open: NaiveTime::from_hms_opt(9, 30, 0).unwrap(),
+                    close: NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+                    open_utc: NaiveTime::from_hms_opt(14, 30, 0).unwrap(),
+                    close_utc: NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
+                    working_days: WEEKDAYS.to_vec(),
+                    holidays: (FALLBACK_FIRST_YEAR..=this_year + 1)
+                        .flat_map(nyse_holidays)
+                        .collect(),
+                }
+            }
+            // A forex day runs from 22:00 UTC, so its local time is UTC+2
+            "FOREX" => Self {
+                exchange: exchange.into(),
+                timezone: None,
+                open: midnight,
+                close: midnight,
+                open_utc: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                close_utc: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                working_days: WEEKDAYS.to_vec(),
+                holidays: BTreeMap::new(),
+            },
+            exchange => Self {
+                exchange: exchange.into(),
+                timezone: None,
+                open: midnight,
+                close: last_minute,
+                open_utc: midnight,
+                close_utc: last_minute,
+                working_days: match exchange {
+                    "CC" => ALL_DAYS.to_vec(),
+                    _ => WEEKDAYS.to_vec(),
+                },
+                holidays: BTreeMap::new(),
+            },
+        }
+    }
+
+    pub fn is_session(&self, date: NaiveDate) -> bool {
+        self.working_days.contains(&date.weekday()) && !self.holidays.contains_key(&date)
+    }
+
+    /// Sessions from `from` to `to`, both inclusive
+    pub fn sessions(&self, from: NaiveDate, to: NaiveDate) -> impl Iterator<Item = NaiveDate> + '_ {
+        from.iter_days()
+            .take_while(move |x| *x <= to)
+            .filter(|x| self.is_session(*x))
+    }
+
+    pub fn session_length(&self) -> TimeDelta {
+        let length = self.close - self.open;
+        if length <= TimeDelta::zero() {
+            length + TimeDelta::days(1)
+        } else {
+            length
+        }
+    }
+
+    /// The UTC open and close on `date`, with daylight saving time if it's in effect then
+    pub fn utc_hours(&self, date: NaiveDate) -> (NaiveTime, NaiveTime) {
+        let shift = daylight_saving_shift(self.timezone.as_deref(), date);
+        (
+            self.open_utc.overflowing_sub_signed(shift).0,
+            self.close_utc.overflowing_sub_signed(shift).0,
+        )
+    }
+
+    /// How far local time is ahead of UTC on `date`, going by the session hours
+    pub fn utc_offset(&self, date: NaiveDate) -> TimeDelta {
+        let offset = self.open - self.utc_hours(date).0;
+        if offset > TimeDelta::hours(14) {
+            offset - TimeDelta::days(1)
+        } else if offset < TimeDelta::hours(-12) {
+            offset + TimeDelta::days(1)
+        } else {
+            offset
+        }
+    }
+
+    /// The local date at `at`
+    pub fn local_date(&self, at: DateTime<Utc>) -> NaiveDate {
+        (at + self.utc_offset(at.date_naive())).date_naive()
+    }
+
+    /// When the session on `date` opens and closes. A session crossing midnight UTC opens the day before
+    pub fn session_bounds(&self, date: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+        let (open_utc, close_utc) = self.utc_hours(date);
+        let open_day = match open_utc >= close_utc {
+            true => date.pred_opt().unwrap_or(date),
+            false => date,
+        };
+        (
+            open_day.and_time(open_utc).and_utc(),
+            date.and_time(close_utc).and_utc(),
+        )
+    }
+
+    /// The session `at` falls into, if any. Sessions may cross midnight UTC
+    pub fn session_at(&self, at: DateTime<Utc>) -> Option<NaiveDate> {
+        let date = at.date_naive();
+        [Some(date), date.succ_opt()]
+            .into_iter()
+            .flatten()
+            .filter(|x| self.is_session(*x))
+            .find(|x| {
+                let (open, close) = self.session_bounds(*x);
+                (open..close).contains(&at)
+            })
+    }
+
+    pub fn is_open(&self, at: DateTime<Utc>) -> bool {
+        self.session_at(at).is_some()
+    }
+
+    /// When the next session after `after` opens, or None if there's none within a year
+    pub fn next_open(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        after
+            .date_naive()
+            .iter_days()
+            .take(367)
+            .filter(|x| self.is_session(*x))
+            .map(|session| self.session_bounds(session).0)
+            .find(|open| *open > after)
+    }
+}
+
+/// `Mon,Tue,Wed,Thu,Fri` as EODHD writes it
+pub fn parse_weekdays(s: &str) -> Vec<Weekday> {
+    s.split(',')
+        .filter_map(|x| x.trim().parse::<Weekday>().ok())
+        .collect()
+}
+
+pub fn format_weekdays(days: &[Weekday]) -> String {
+    days.iter()
+        .map(Weekday::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/**
+ * How far clocks in `timezone` are ahead of standard time on `date`, as the time zone database has it.
+ * An unknown time zone is taken to have no daylight saving time.
+ */
+fn daylight_saving_shift(timezone: Option<&str>, date: NaiveDate) -> TimeDelta {
+    let Some(timezone) = timezone.and_then(|x| x.parse::<Tz>().ok()) else {
+        return TimeDelta::zero();
+    };
+    let noon = date.and_hms_opt(12, 0, 0).unwrap();
+    timezone.offset_from_utc_datetime(&noon).dst_offset()
+}
+
+/// The days NYSE is closed in `year`, with holidays on a weekend moved to the closest weekday
+fn nyse_holidays(year: i32) -> Vec<(NaiveDate, Box<str>)> {
+    let date = |month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+    let nth = |month, weekday, n| NaiveDate::from_weekday_of_month_opt(year, month, weekday, n);
+    let observed = |date: NaiveDate| match date.weekday() {
+        Weekday::Sat => date - TimeDelta::days(1),
+        Weekday::Sun => date + TimeDelta::days(1),
+        _ => date,
+    };
+    let last_monday_of_may = nth(5, Weekday::Mon, 5).or(nth(5, Weekday::Mon, 4));
+
+    let mut holidays = vec![
+        (nth(1, Weekday::Mon, 3), "Martin Luther King Jr. Day"),
+        (nth(2, Weekday::Mon, 3), "Washington's Birthday"),
+        (Some(easter(year) - TimeDelta::days(2)), "Good Friday"),
+        (last_monday_of_may, "Memorial Day"),
+        (Some(observed(date(7, 4))), "Independence Day"),
+        (nth(9, Weekday::Mon, 1), "Labor Day"),
+        (nth(11, Weekday::Thu, 4), "Thanksgiving Day"),
+        (Some(observed(date(12, 25))), "Christmas Day"),
+    ];
+    // NYSE doesn't close on the Friday before when New Year's Day is a Saturday
+    if date(1, 1).weekday() != Weekday::Sat {
+        holidays.push((Some(observed(date(1, 1))), "New Year's Day"));
+    }
+    if year >= 2022 {
+        holidays.push((Some(observed(date(6, 19))), "Juneteenth"));
+    }
+    for (closed_year, month, day, name) in NYSE_SPECIAL_CLOSURES {
+        if *closed_year == year {
+            holidays.push((Some(date(*month, *day)), name));
+        }
+    }
+
+    holidays
+        .into_iter()
+        .filter_map(|(date, name)| Some((date?, name.into())))
+        .collect()
+}
+
+/// Easter Sunday, by the anonymous Gregorian algorithm
+fn easter(year: i32) -> NaiveDate {
+    let (a, b, c) = (year % 19, year / 100, year % 100);
+    let (d, e) = (b / 4, b % 4);
+    let g = (b - (b + 8) / 25 + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let l = (32 + 2 * e + 2 * (c / 4) - h - c % 4) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let n = h + l - 7 * m + 114;
+    NaiveDate::from_ymd_opt(year, (n / 31) as u32, (n % 31 + 1) as u32).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        date(year, month, day)
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn easter_sundays() {
+        let easters = [
+            (2000, 4, 23),
+            (2019, 4, 21),
+            (2020, 4, 12),
+            (2021, 4, 4),
+            (2022, 4, 17),
+            (2023, 4, 9),
+            (2024, 3, 31),
+            (2025, 4, 20),
+            (2026, 4, 5),
+            (2038, 4, 25),
+        ];
+        for (year, month, day) in easters {
+            assert_eq!(easter(year), date(year, month, day));
+        }
+    }
+
+    /// As published by NYSE
+    #[test]
+    fn nyse_holidays_2020_to_2026() {
+        let published: &[(i32, &[(u32, u32)])] = &[
+            (
+                2020,
+                &[
+                    (1, 1),
+                    (1, 20),
+                    (2, 17),
+                    (4, 10),
+                    (5, 25),
+                    (7, 3),
+                    (9, 7),
+                    (11, 26),
+                    (12, 25),
+                ],
+            ),
+            (
+                2021,
+                &[
+                    (1, 1),
+                    (1, 18),
+                    (2, 15),
+                    (4, 2),
+                    (5, 31),
+                    (7, 5),
+                    (9, 6),
+                    (11, 25),
+                    (12, 24),
+                ],
+            ),
+            (
+                2022,
+                &[
+                    (1, 17),
+                    (2, 21),
+                    (4, 15),
+                    (5, 30),
+                    (6, 20),
+                    (7, 4),
+                    (9, 5),
+                    (11, 24),
+                    (12, 26),
+                ],
+            ),
+            (
+                2023,
+                &[
+                    (1, 2),
+                    (1, 16),
+                    (2, 20),
+                    (4, 7),
+                    (5, 29),
+                    (6, 19),
+                    (7, 4),
+                    (9, 4),
+                    (11, 23),
+                    (12, 25),
+                ],
+            ),
+            (
+                2024,
+                &[
+                    (1, 1),
+                    (1, 15),
+                    (2, 19),
+                    (3, 29),
+                    (5, 27),
+                    (6, 19),
+                    (7, 4),
+                    (9, 2),
+                    (11, 28),
+                    (12, 25),
+                ],
+            ),
+            (
+                2025,
+                &[
+                    (1, 1),
+                    (1, 9),
+                    (1, 20),
+                    (2, 17),
+                    (4, 18),
+                    (5, 26),
+                    (6, 19),
+                    (7, 4),
+                    (9, 1),
+                    (11, 27),
+                    (12, 25),
+                ],
+            ),
+            (
+                2026,
+                &[
+                    (1, 1),
+                    (1, 19),
+                    (2, 16),
+                    (4, 3),
+                    (5, 25),
+                    (6, 19),
+                    (7, 3),
+                    (9, 7),
+                    (11, 26),
+                    (12, 25),
+                ],
+            ),
+        ];
+        for (year, days) in published {
+            let mut holidays = nyse_holidays(*year)
+                .into_iter()
+                .map(|(date, _)| date)
+                .collect::<Vec<_>>();
+            holidays.sort();
+            let expected = days
+                .iter()
+                .map(|(month, day)| date(*year, *month, *day))
+                .collect::<Vec<_>>();
+            assert_eq!(holidays, expected, "{year}");
+        }
+    }
+
+    #[test]
+    fn us_hours_follow_daylight_saving_time() {
+        let calendar = TradingCalendar::fallback("US");
+        let nine_thirty = |hour| NaiveTime::from_hms_opt(hour, 30, 0).unwrap();
+
+        assert_eq!(calendar.utc_hours(date(2024, 1, 2)).0, nine_thirty(14));
+        assert_eq!(calendar.utc_hours(date(2024, 3, 8)).0, nine_thirty(14));
+        assert_eq!(calendar.utc_hours(date(2024, 3, 11)).0, nine_thirty(13));
+        assert_eq!(calendar.utc_hours(date(2024, 11, 1)).0, nine_thirty(13));
+        assert_eq!(calendar.utc_hours(date(2024, 11, 4)).0, nine_thirty(14));
+
+        assert!(calendar.is_open(at(2024, 7, 1, 13, 30)));
+        assert!(!calendar.is_open(at(2024, 7, 1, 20, 30)));
+        assert!(!calendar.is_open(at(2024, 1, 2, 14, 0)));
+        assert!(calendar.is_open(at(2024, 1, 2, 20, 30)));
+        assert!(!calendar.is_open(at(2025, 1, 9, 15, 0)));
+
+        // Independence Day falls in between
+        assert_eq!(
+            calendar.next_open(at(2024, 7, 3, 21, 0)),
+            Some(at(2024, 7, 5, 13, 30))
+        );
+        assert_eq!(
+            calendar.next_open(at(2024, 12, 31, 21, 0)),
+            Some(at(2025, 1, 2, 14, 30))
+        );
+    }
+
+    #[test]
+    fn eodhd_hours_are_stored_outside_daylight_saving_time() {
+        let details: ExchangeDetails = serde_json::from_str(
+            r#"{
+                "Code": "LSE",
+                "Timezone": "Europe/London",
+                "TradingHours": {"Open": "08:00:00", "Close": "16:30:00", "OpenUTC": "07:00:00",
+                    "CloseUTC": "15:30:00", "WorkingDays": "Mon,Tue,Wed,Thu,Fri"}
+            }"#,
+        )
+        .unwrap();
+        let calendar = TradingCalendar::from_details(details, date(2024, 7, 1));
+        assert_eq!(calendar.open_utc, NaiveTime::from_hms_opt(8, 0, 0).unwrap());
+        assert_eq!(
+            calendar.session_bounds(date(2024, 7, 2)),
+            (at(2024, 7, 2, 7, 0), at(2024, 7, 2, 15, 30))
+        );
+        assert_eq!(
+            calendar.session_bounds(date(2024, 12, 2)),
+            (at(2024, 12, 2, 8, 0), at(2024, 12, 2, 16, 30))
+        );
+    }
+
+    #[test]
+    fn sessions_may_cross_midnight_utc() {
+        let calendar = TradingCalendar {
+            open_utc: NaiveTime::from_hms_opt(23, 0, 0).unwrap(),
+            close_utc: NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+            ..TradingCalendar::fallback("AU")
+        };
+        // Monday's session opens on Sunday evening UTC
+        assert_eq!(
+            calendar.session_at(at(2024, 7, 7, 23, 30)),
+            Some(date(2024, 7, 8))
+        );
+        assert_eq!(
+            calendar.session_at(at(2024, 7, 8, 4, 0)),
+            Some(date(2024, 7, 8))
+        );
+        assert_eq!(calendar.session_at(at(2024, 7, 8, 12, 0)), None);
+        // Friday evening UTC belongs to Saturday, which doesn't trade
+        assert_eq!(calendar.session_at(at(2024, 7, 12, 23, 30)), None);
+        assert_eq!(
+            calendar.next_open(at(2024, 7, 12, 5, 0)),
+            Some(at(2024, 7, 14, 23, 0))
+        );
+    }
+
+    #[test]
+    fn daylight_saving_time_comes_from_the_time_zone() {
+        let shift = |timezone, month| daylight_saving_shift(Some(timezone), date(2024, month, 15));
+        assert_eq!(shift("Europe/Berlin", 7), TimeDelta::hours(1));
+        assert_eq!(shift("Europe/Berlin", 1), TimeDelta::zero());
+        // Southern summer
+        assert_eq!(shift("Australia/Sydney", 1), TimeDelta::hours(1));
+        assert_eq!(shift("Australia/Sydney", 7), TimeDelta::zero());
+        assert_eq!(shift("Asia/Tokyo", 7), TimeDelta::zero());
+        assert_eq!(shift("Nowhere/Special", 7), TimeDelta::zero());
+        assert_eq!(
+            daylight_saving_shift(None, date(2024, 7, 15)),
+            TimeDelta::zero()
+        );
+    }
+
+    #[test]
+    fn forex_trades_from_sunday_to_friday_evening() {
+        let calendar = TradingCalendar::fallback("FOREX");
+        assert!(!calendar.is_open(at(2024, 7, 7, 21, 0)));
+        assert_eq!(
+            calendar.session_at(at(2024, 7, 7, 22, 0)),
+            Some(date(2024, 7, 8))
+        );
+        assert_eq!(calendar.local_date(at(2024, 7, 7, 22, 0)), date(2024, 7, 8));
+        assert!(calendar.is_open(at(2024, 7, 12, 21, 55)));
+        assert!(!calendar.is_open(at(2024, 7, 12, 22, 0)));
+        assert!(!calendar.is_open(at(2024, 7, 13, 12, 0)));
+        assert_eq!(calendar.session_length(), TimeDelta::days(1));
+        assert_eq!(
+            calendar.next_open(at(2024, 7, 12, 22, 0)),
+            Some(at(2024, 7, 14, 22, 0))
+        );
+    }
+}
@@ -0,0 +1,410 @@
+use anyhow::{bail, Result};
+use chrono::NaiveDateTime;
+use colored::Colorize;
+use futures::stream::{BoxStream, StreamExt};
+use sqlx::FromRow;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::models::Intraday;
+
+/// What a price has to satisfy to be trusted. Parsed from the names `as_str` gives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    HighBelowLow,
+    NegativeVolume,
+    /// Any of open, high, low or close at or below zero
+    ZeroPrice,
+    /// A close `spike_factor` times off from both its neighbours
+    Spike,
+    /// A second bar with the same timestamp
+    Duplicate,
+}
+
+impl Rule {
+    pub const ALL: &'static [Rule] = &[
+        Rule::HighBelowLow,
+        Rule::NegativeVolume,
+        Rule::ZeroPrice,
+        Rule::Spike,
+        Rule::Duplicate,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::HighBelowLow => "high-below-low",
+            Self::NegativeVolume => "negative-volume",
+            Self::ZeroPrice => "zero-price",
+            Self::Spike => "spike",
+            Self::Duplicate => "duplicate",
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Self::ALL.iter().find(|x| x.as_str() == s.to_lowercase()) {
+            Some(rule) => Ok(*rule),
+            None => bail!(
+                "Unknown rule '{s}'. Expected one of {}",
+                Self::ALL
+                    .iter()
+                    .map(Rule::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The table a price is stored in. The names match `QuarantinedPrice.source`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceTable {
+    /// `StockPrice`
+    Intraday,
+    /// `StockPriceEOD`
+    Eod,
+}
+
+impl PriceTable {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Intraday => "INTRADAY",
+            Self::Eod => "EOD",
+        }
+    }
+}
+
+/// A price as stored, NULLs and all. Daily prices have their date at midnight as `timestamp`
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct PriceRow {
+    pub timestamp: NaiveDateTime,
+    pub gmtoffset: Option<i32>,
+    pub open: Option<f64>,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub close: Option<f64>,
+    pub adjusted_close: Option<f64>,
+    pub volume: Option<i64>,
+}
+
+impl From<&Intraday> for PriceRow {
+    fn from(intraday: &Intraday) -> Self {
+        Self {
+            timestamp: intraday.datetime.naive_utc(),
+            gmtoffset: Some(intraday.gmt_offset),
+            open: Some(intraday.open),
+            high: Some(intraday.high),
+            low: Some(intraday.low),
+            close: Some(intraday.close),
+            adjusted_close: None,
+            volume: intraday.volume,
+        }
+    }
+}
+
+/// Which rules to check, and how far a close may jump before it's a spike
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validator {
+    pub rules: Vec<Rule>,
+    pub spike_factor: f64,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self {
+            rules: Rule::ALL.to_vec(),
+            spike_factor: 100.0,
+        }
+    }
+}
+
+impl Validator {
+    /**
+     * The rows of `rows`, which must be sorted by timestamp, that break a rule, by index.
+     * Only the first rule a row breaks is reported. Missing values don't break any rule.
+     */
+    pub fn check(&self, rows: &[PriceRow]) -> Vec<(usize, Rule)> {
+        let closes = rows.iter().map(|x| x.close).collect::<Vec<_>>();
+        let mut seen = HashSet::new();
+
+        rows.iter()
+            .enumerate()
+            .filter_map(|(i, row)| {
+                let is_new = seen.insert(row.timestamp);
+                let rule = self.rules.iter().find(|rule| match rule {
+                    Rule::HighBelowLow => {
+                        matches!((row.high, row.low), (Some(h), Some(l)) if h < l)
+                    }
+                    Rule::NegativeVolume => row.volume.is_some_and(|v| v < 0),
+                    Rule::ZeroPrice => [row.open, row.high, row.low, row.close]
+                        .iter()
+                        .any(|x| x.is_some_and(|x| x <= 0.0)),
+                    Rule::Spike => self.is_spike(&closes, i),
+                    Rule::Duplicate => !is_new,
+                })?;
+                Some((i, *rule))
+            })
+            .collect()
+    }
+
+    /**
+     * Like `check`, for rows read `batch` at a time from a stream sorted by timestamp. Gives the rows
+     * that break a rule, so a symbol's whole history never has to be in memory.
+     */
+    pub async fn check_stream<E>(
+        &self,
+        rows: BoxStream<'_, Result<PriceRow, E>>,
+        batch: usize,
+    ) -> Result<Vec<(PriceRow, Rule)>, E> {
+        let mut batches = rows.chunks(batch.max(1));
+        // Rows before `judged` were already checked, but are still a neighbour or a duplicate's original
+        let (mut window, mut judged) = (Vec::new(), 0);
+        let mut violations = Vec::new();
+        loop {
+            let next = batches.next().await;
+            let done = next.is_none();
+            if let Some(next) = next {
+                window.extend(next.into_iter().collect::<Result<Vec<_>, E>>()?);
+            }
+            // The last row is a spike or not depending on the row after it
+            let until = match done {
+                true => window.len(),
+                false => window.len().saturating_sub(1),
+            };
+            violations.extend(
+                self.check(&window)
+                    .into_iter()
+                    .filter(|(i, _)| (judged..until).contains(i))
+                    .map(|(i, rule)| (window[i].clone(), rule)),
+            );
+            if done {
+                return Ok(violations);
+            }
+            let keep_from = until.saturating_sub(1);
+            window.drain(..keep_from);
+            judged = until - keep_from;
+        }
+    }
+
+    /// A close that is far off from the closes around it. Neighbours without a close are ignored
+    fn is_spike(&self, closes: &[Option<f64>], i: usize) -> bool {
+        let Some(close) = closes[i].filter(|x| *x > 0.0) else {
+            return false;
+        };
+        let neighbours = [i.checked_sub(1), Some(i + 1)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| closes.get(i).copied().flatten())
+            .filter(|x| *x > 0.0)
+            .collect::<Vec<_>>();
+        !neighbours.is_empty()
+            && neighbours
+                .iter()
+                .all(|other| (close / other).max(other / close) >= self.spike_factor)
+    }
+}
+
+/**
+ * Splits `prices` into what `validator` lets through and what it rejects, with the rule each broke.
+ * Everything passes without a validator.
+ */
+pub fn gate<'a>(
+    validator: Option<&Validator>,
+    code: &str,
+    exchange: &str,
+    prices: &'a [Intraday],
+) -> (Vec<&'a Intraday>, Vec<(PriceRow, Rule)>) {
+    let Some(validator) = validator else {
+        return (prices.iter().collect(), Vec::new());
+    };
+    let rows = prices.iter().map(PriceRow::from).collect::<Vec<_>>();
+    let mut rejected = validator.check(&rows).into_iter().peekable();
+
+    let mut passed = Vec::with_capacity(prices.len());
+    let mut quarantined = Vec::new();
+    for (i, (price, row)) in prices.iter().zip(rows).enumerate() {
+        match rejected.next_if(|(index, _)| *index == i) {
+            Some((_, rule)) => quarantined.push((row, rule)),
+            None => passed.push(price),
+        }
+    }
+    if !quarantined.is_empty() {
+        eprintln!(
+            "[{}] {}.{} rejected {} of {} bars",
+            "GATE".bold().yellow(),
+            code,
+            exchange,
+            quarantined.len(),
+            prices.len()
+        );
+    }
+    (passed, quarantined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use futures::stream;
+
+    /// A clean bar at minute `minute` closing at `close`
+    fn row(minute: u32, close: f64) -> PriceRow {
+        PriceRow {
+            timestamp: NaiveDate::from_ymd_opt(2024, 7, 1)
+                .unwrap()
+                .and_hms_opt(14, minute, 0)
+                .unwrap(),
+            gmtoffset: Some(0),
+            open: Some(close),
+            high: Some(close + 1.0),
+            low: Some(close - 1.0),
+            close: Some(close),
+            adjusted_close: None,
+            volume: Some(100),
+        }
+    }
+
+    fn broken() -> Vec<PriceRow> {
+        let mut rows = (0..10).map(|i| row(i * 5, 10.0)).collect::<Vec<_>>();
+        rows[1].high = Some(8.0);
+        rows[2].volume = Some(-1);
+        rows[3].open = Some(0.0);
+        rows[5].close = Some(5000.0);
+        rows[7] = rows[6].clone();
+        // Breaks both zero-price and high-below-low
+        rows[9].low = Some(12.0);
+        rows[9].open = Some(-1.0);
+        rows
+    }
+
+    #[test]
+    fn finds_every_rule() {
+        assert_eq!(
+            Validator::default().check(&broken()),
+            [
+                (1, Rule::HighBelowLow),
+                (2, Rule::NegativeVolume),
+                (3, Rule::ZeroPrice),
+                (5, Rule::Spike),
+                (7, Rule::Duplicate),
+                (9, Rule::HighBelowLow),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_checks_its_rules() {
+        let validator = Validator {
+            rules: vec![Rule::ZeroPrice, Rule::Duplicate],
+            ..Default::default()
+        };
+        assert_eq!(
+            validator.check(&broken()),
+            [
+                (3, Rule::ZeroPrice),
+                (7, Rule::Duplicate),
+                (9, Rule::ZeroPrice)
+            ]
+        );
+    }
+
+    #[test]
+    fn spikes_are_far_from_every_neighbour() {
+        let validator = Validator {
+            rules: vec![Rule::Spike],
+            spike_factor: 10.0,
+        };
+        // A jump that holds is a new level, not a spike
+        let level = [row(0, 10.0), row(5, 10.0), row(10, 100.0), row(15, 100.0)];
+        assert!(validator.check(&level).is_empty());
+        // Just under the factor
+        let close = [row(0, 10.0), row(5, 99.0), row(10, 10.0)];
+        assert!(validator.check(&close).is_empty());
+        // Down as well as up, and at the edges with a single neighbour
+        let spikes = [
+            row(0, 500.0),
+            row(5, 10.0),
+            row(10, 10.0),
+            row(15, 1.0),
+            row(20, 10.0),
+            row(25, 10.0),
+        ];
+        assert_eq!(
+            validator.check(&spikes),
+            [(0, Rule::Spike), (3, Rule::Spike)]
+        );
+        // Neighbours without a close don't count
+        let mut missing = [row(0, 10.0), row(5, 1000.0), row(10, 10.0)];
+        missing[0].close = None;
+        missing[2].close = None;
+        assert!(validator.check(&missing).is_empty());
+    }
+
+    #[test]
+    fn missing_values_break_nothing() {
+        let mut empty = row(0, 10.0);
+        (empty.open, empty.high, empty.low, empty.close, empty.volume) =
+            (None, None, None, None, None);
+        assert!(Validator::default().check(&[empty]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn streaming_finds_the_same_rows() {
+        let validator = Validator::default();
+        let rows = broken();
+        let expected = validator
+            .check(&rows)
+            .into_iter()
+            .map(|(i, rule)| (rows[i].clone(), rule))
+            .collect::<Vec<_>>();
+        for batch in 1..=rows.len() + 1 {
+            let stream = stream::iter(rows.clone().into_iter().map(Ok::<_, ()>)).boxed();
+            assert_eq!(
+                validator.check_stream(stream, batch).await,
+                Ok(expected.clone()),
+                "batch of {batch}"
+            );
+        }
+    }
+
+    #[test]
+    fn gate_splits_off_what_breaks_a_rule() {
+        let at = |minute: i64| {
+            NaiveDate::from_ymd_opt(2024, 7, 1)
+                .unwrap()
+                .and_hms_opt(14, 0, 0)
+                .unwrap()
+                .and_utc()
+                + chrono::TimeDelta::minutes(minute)
+        };
+        let bar = |minute, high| Intraday {
+            timestamp: at(minute).timestamp(),
+            gmt_offset: 0,
+            datetime: at(minute),
+            open: 10.0,
+            high,
+            low: 9.0,
+            close: 10.0,
+            volume: Some(100),
+        };
+        let prices = [bar(0, 11.0), bar(5, 8.0), bar(10, 11.0)];
+
+        let (passed, rejected) = gate(None, "AAA", "US", &prices);
+        assert_eq!((passed.len(), rejected.len()), (3, 0));
+
+        let (passed, rejected) = gate(Some(&Validator::default()), "AAA", "US", &prices);
+        let passed = passed.iter().map(|x| x.timestamp).collect::<Vec<_>>();
+        assert_eq!(passed, [prices[0].timestamp, prices[2].timestamp]);
+        assert_eq!(rejected, [(PriceRow::from(&prices[1]), Rule::HighBelowLow)]);
+    }
+}